@@ -4,12 +4,22 @@ use clap::{Subcommand, ValueEnum};
 use owo_colors::{OwoColorize, Stream::Stdout, Style};
 use std::io::Write;
 use std::{fs::File, time::Instant};
-use webgestalt_lib::methods::gsea::GSEAConfig;
+use webgestalt_lib::methods::gsea::phenotype::{
+    gsea_phenotype_with_excluded, rank_metric, PhenotypeGSEAConfig, RankMetric,
+};
+use webgestalt_lib::methods::gsea::prepare::{
+    prepare_rank_list, DuplicatePolicy, NonFinitePolicy, RankListConfig, TiePolicy,
+};
+use webgestalt_lib::methods::gsea::{
+    enrichment_plot_data, GSEAConfig, RunningSumStorage, WeightingScheme,
+};
 use webgestalt_lib::methods::multilist::{combine_gmts, MultiListMethod, NormalizationMethod};
 use webgestalt_lib::methods::nta::NTAConfig;
-use webgestalt_lib::methods::ora::ORAConfig;
+use webgestalt_lib::methods::ora::{ORAConfig, ORAResult, ORATestDirection};
+use webgestalt_lib::methods::redundancy::{reduce_redundancy, EnrichedSet, RedundancyMethod};
 use webgestalt_lib::readers::utils::Item;
 use webgestalt_lib::readers::{read_gmt_file, read_rank_file};
+use webgestalt_lib::writers::save_gsea_plot_data;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct CliArgs {
@@ -48,7 +58,8 @@ enum ExampleOptions {
 
 #[derive(Parser)]
 struct NtaArgs {
-    /// Path to the file containing the network (tab separated file with two columns: source and target)
+    /// Path to the file containing the network (tab separated file with two columns: source and
+    /// target, and an optional third column of positive edge weights)
     #[arg(short, long)]
     network: String,
     /// Path to the file containing the seeds (one per line)
@@ -70,6 +81,12 @@ struct NtaArgs {
     /// Options: prioritize, expand
     #[arg(short, long, default_value = "prioritize")]
     method: NTAMethodClap,
+    /// Only walk edges from source to target
+    #[arg(long)]
+    directed: bool,
+    /// Path to a GMT file for ORA of the neighborhood, using the network nodes as reference
+    #[arg(short, long)]
+    gmt: Option<String>,
 }
 
 #[derive(ValueEnum, Clone)]
@@ -84,11 +101,98 @@ struct GseaArgs {
     #[arg(short, long)]
     gmt: String,
     /// Path to the rank file of interest
-    #[arg(short, long)]
-    rnk: String,
+    #[arg(short, long, required_unless_present = "gct", conflicts_with = "gct")]
+    rnk: Option<String>,
+    /// Path to a GCT expression matrix, for GSEA that permutes sample labels
+    #[arg(long, requires = "cls")]
+    gct: Option<String>,
+    /// Path to the CLS phenotype file of the GCT samples
+    #[arg(long, requires = "gct")]
+    cls: Option<String>,
+    /// Metric to rank the analytes of the GCT by phenotype
+    #[arg(long, default_value = "signal-to-noise")]
+    metric: RankMetricClap,
     /// Output path for the results
     #[arg(short, long, default_value = "out.json")]
     output: String,
+    /// Weighting scheme of the enrichment score
+    #[arg(long, default_value = "weighted")]
+    scheme: WeightingSchemeClap,
+    /// Seed for the permutations. A random seed is used and reported if not provided
+    #[arg(long)]
+    seed: Option<u64>,
+    /// How much of the running sum of every set to keep in the output
+    #[arg(long, default_value = "none")]
+    running_sum: RunningSumClap,
+    /// Number of running sum points to keep with `--running-sum downsampled`
+    #[arg(long, default_value = "1000")]
+    running_sum_points: usize,
+    /// How to merge analytes that appear more than once in the rank file
+    #[arg(long, default_value = "max-abs")]
+    duplicates: DuplicatePolicyClap,
+    /// How to order analytes with the same rank. Random order uses the seed, or 0 if not set
+    #[arg(long, default_value = "name")]
+    ties: TiePolicyClap,
+    /// How to handle NaN and infinite ranks
+    #[arg(long, default_value = "drop")]
+    non_finite: NonFinitePolicyClap,
+    /// Comma-separated names of sets to export enrichment plot data for
+    #[arg(long, value_delimiter = ',')]
+    plot_sets: Vec<String>,
+    /// Output path for the enrichment plot data
+    #[arg(long, default_value = "plot.json")]
+    plot_output: String,
+    /// Output path for the sets outside the overlap limits, with their overlap and reason
+    #[arg(long)]
+    excluded_output: Option<String>,
+    #[command(flatten)]
+    redundancy: RedundancyArgs,
+}
+
+#[derive(ValueEnum, Clone)]
+enum DuplicatePolicyClap {
+    MaxAbs,
+    Mean,
+    First,
+    Error,
+}
+
+#[derive(ValueEnum, Clone)]
+enum TiePolicyClap {
+    Name,
+    Random,
+}
+
+#[derive(ValueEnum, Clone)]
+enum NonFinitePolicyClap {
+    Drop,
+    Error,
+}
+
+#[derive(ValueEnum, Clone)]
+enum RunningSumClap {
+    None,
+    Downsampled,
+    Full,
+}
+
+#[derive(ValueEnum, Clone)]
+enum WeightingSchemeClap {
+    Classic,
+    Weighted,
+    WeightedP2,
+    #[value(name = "weighted-p1.5")]
+    WeightedP1_5,
+}
+
+#[derive(ValueEnum, Clone)]
+enum RankMetricClap {
+    SignalToNoise,
+    TTest,
+    Ratio,
+    Log2Ratio,
+    Difference,
+    Pearson,
 }
 #[derive(Parser)]
 struct ORAArgs {
@@ -104,6 +208,47 @@ struct ORAArgs {
     /// Path the file containing the reference list
     #[arg(short, long)]
     reference: String,
+    /// Do not report the overlapping analytes of each set
+    #[arg(long)]
+    compact: bool,
+    /// Direction of the test
+    #[arg(long, default_value = "over")]
+    direction: ORADirectionClap,
+    /// Minimum overlap with the interest list for a set to be tested. Defaults to 5 for `over`,
+    /// and to 0 for `under` and `two-sided` so depleted sets are still tested
+    #[arg(long)]
+    min_overlap: Option<i64>,
+    /// Output path for the sets excluded by the size and overlap filters, with their reasons
+    #[arg(long)]
+    excluded_output: Option<String>,
+    #[command(flatten)]
+    redundancy: RedundancyArgs,
+}
+
+#[derive(ValueEnum, Clone)]
+enum ORADirectionClap {
+    Over,
+    Under,
+    TwoSided,
+}
+
+#[derive(Args)]
+struct RedundancyArgs {
+    /// Collapse redundant significant sets with the chosen method
+    #[arg(long)]
+    redundancy: Option<RedundancyMethodClap>,
+    /// Maximum number of sets to select with weighted set cover
+    #[arg(long, default_value = "10")]
+    top_n: usize,
+    /// Output path for the redundancy clusters
+    #[arg(long, default_value = "redundancy.json")]
+    redundancy_output: String,
+}
+
+#[derive(ValueEnum, Clone)]
+enum RedundancyMethodClap {
+    AffinityPropagation,
+    WeightedSetCover,
 }
 
 #[derive(Args)]
@@ -180,6 +325,25 @@ fn check_and_overwrite(file_path: &str) {
     }
 }
 
+fn save_redundancy<T: EnrichedSet>(significant: &[T], gmt: &[Item], args: &RedundancyArgs) {
+    let method = match args.redundancy {
+        Some(RedundancyMethodClap::AffinityPropagation) => RedundancyMethod::AffinityPropagation,
+        Some(RedundancyMethodClap::WeightedSetCover) => {
+            RedundancyMethod::WeightedSetCover(args.top_n)
+        }
+        None => return,
+    };
+    check_and_overwrite(&args.redundancy_output);
+    let clusters = reduce_redundancy(significant, gmt, method);
+    let output_file = File::create(&args.redundancy_output).expect("Could not create output file!");
+    serde_json::to_writer(output_file, &clusters).expect("Could not create JSON file!");
+    println!(
+        "Reduced {} significant analyte sets to {} representative sets",
+        significant.len(),
+        clusters.len()
+    );
+}
+
 fn main() {
     println!("WebGestalt CLI v{}", env!("CARGO_PKG_VERSION"));
     let args = CliArgs::parse();
@@ -238,34 +402,146 @@ fn main() {
         },
         Some(Commands::Gsea(gsea_args)) => {
             check_and_overwrite(&gsea_args.output);
-            let gene_list = webgestalt_lib::readers::read_rank_file(gsea_args.rnk.clone())
-                .unwrap_or_else(|_| {
-                    panic!("File {} not found", gsea_args.rnk.clone());
-                });
+            if let Some(path) = &gsea_args.excluded_output {
+                check_and_overwrite(path);
+            }
+            if !gsea_args.plot_sets.is_empty() {
+                check_and_overwrite(&gsea_args.plot_output);
+            }
             let gmt = webgestalt_lib::readers::read_gmt_file(gsea_args.gmt.clone()).unwrap_or_else(
                 |_| {
                     panic!("File {} not found", gsea_args.gmt.clone());
                 },
             );
-            let res =
-                webgestalt_lib::methods::gsea::gsea(gene_list, gmt, GSEAConfig::default(), None);
+            let scheme = match gsea_args.scheme {
+                WeightingSchemeClap::Classic => WeightingScheme::Classic,
+                WeightingSchemeClap::Weighted => WeightingScheme::Weighted,
+                WeightingSchemeClap::WeightedP2 => WeightingScheme::WeightedP2,
+                WeightingSchemeClap::WeightedP1_5 => WeightingScheme::WeightedP1_5,
+            };
+            let running_sum = match gsea_args.running_sum {
+                RunningSumClap::None => RunningSumStorage::None,
+                RunningSumClap::Downsampled => {
+                    RunningSumStorage::Downsampled(gsea_args.running_sum_points)
+                }
+                RunningSumClap::Full => RunningSumStorage::Full,
+            };
+            let config = GSEAConfig {
+                p: scheme.p(),
+                seed: gsea_args.seed,
+                running_sum,
+                ..Default::default()
+            };
+            let ((res, excluded), rank_list) = match (&gsea_args.gct, &gsea_args.cls) {
+                (Some(gct), Some(cls)) => {
+                    let matrix = webgestalt_lib::readers::read_gct_file(gct.clone())
+                        .unwrap_or_else(|e| panic!("Could not read {}: {}", gct, e));
+                    let phenotype = webgestalt_lib::readers::read_cls_file(cls.clone())
+                        .unwrap_or_else(|e| panic!("Could not read {}: {}", cls, e));
+                    let metric = match gsea_args.metric {
+                        RankMetricClap::SignalToNoise => RankMetric::SignalToNoise,
+                        RankMetricClap::TTest => RankMetric::TTest,
+                        RankMetricClap::Ratio => RankMetric::Ratio,
+                        RankMetricClap::Log2Ratio => RankMetric::Log2Ratio,
+                        RankMetricClap::Difference => RankMetric::Difference,
+                        RankMetricClap::Pearson => RankMetric::Pearson,
+                    };
+                    let res = gsea_phenotype_with_excluded(
+                        &matrix,
+                        &phenotype,
+                        gmt.clone(),
+                        PhenotypeGSEAConfig {
+                            gsea: config.clone(),
+                            metric,
+                        },
+                    );
+                    (res, rank_metric(&matrix, &phenotype, metric))
+                }
+                _ => {
+                    let rnk = gsea_args.rnk.clone().unwrap();
+                    let gene_list = webgestalt_lib::readers::read_rank_file(rnk.clone())
+                        .unwrap_or_else(|e| {
+                            panic!("Could not read {}: {}", rnk, e);
+                        });
+                    let rank_config = RankListConfig {
+                        duplicates: match gsea_args.duplicates {
+                            DuplicatePolicyClap::MaxAbs => DuplicatePolicy::MaxAbs,
+                            DuplicatePolicyClap::Mean => DuplicatePolicy::Mean,
+                            DuplicatePolicyClap::First => DuplicatePolicy::First,
+                            DuplicatePolicyClap::Error => DuplicatePolicy::Error,
+                        },
+                        ties: match gsea_args.ties {
+                            TiePolicyClap::Name => TiePolicy::ByName,
+                            TiePolicyClap::Random => TiePolicy::Random {
+                                seed: gsea_args.seed.unwrap_or(0),
+                            },
+                        },
+                        non_finite: match gsea_args.non_finite {
+                            NonFinitePolicyClap::Drop => NonFinitePolicy::Drop,
+                            NonFinitePolicyClap::Error => NonFinitePolicy::Error,
+                        },
+                    };
+                    let (gene_list, report) = prepare_rank_list(gene_list, &rank_config)
+                        .unwrap_or_else(|e| panic!("Could not prepare {}: {}", rnk, e));
+                    println!(
+                        "Rank list: {} of {} entries kept, {} non-finite dropped, {} duplicate analytes merged, {} analytes in {} ties",
+                        report.output_size,
+                        report.input_size,
+                        report.dropped.len(),
+                        report.duplicates.len(),
+                        report.tied_analytes,
+                        report.tie_groups
+                    );
+                    let res = webgestalt_lib::methods::gsea::gsea_with_excluded(
+                        gene_list.clone(),
+                        gmt.clone(),
+                        config.clone(),
+                        None,
+                    );
+                    (res, gene_list)
+                }
+            };
+            if !gsea_args.plot_sets.is_empty() {
+                let plot_data =
+                    enrichment_plot_data(rank_list, &gmt, &gsea_args.plot_sets, &config);
+                save_gsea_plot_data(gsea_args.plot_output.clone(), &plot_data)
+                    .expect("Could not create plot data file!");
+                println!(
+                    "Saved enrichment plot data of {} sets to {}",
+                    plot_data.sets.len(),
+                    gsea_args.plot_output
+                );
+            }
             let output_file =
                 File::create(&gsea_args.output).expect("Could not create output file!");
             serde_json::to_writer(output_file, &res).expect("Could not create JSON file!");
-            let mut count = 0;
+            if let Some(path) = &gsea_args.excluded_output {
+                webgestalt_lib::writers::save_gsea_excluded(path.clone(), &excluded)
+                    .expect("Could not create excluded sets file!");
+                println!("Saved {} excluded sets to {}", excluded.len(), path);
+            }
+            if let Some(seed) = res.first().and_then(|x| x.seed) {
+                println!("Permutation seed: {}", seed);
+            }
+            let mut significant = Vec::new();
             for i in res {
                 if i.p < 0.05 && i.fdr < 0.05 {
                     println!("{}: {}, {}", i.set, i.p, i.fdr);
-                    count += 1;
+                    significant.push(i);
                 }
             }
             println!(
-                "Done with GSEA and found {} significant analyte sets",
-                count
+                "Done with GSEA and found {} significant analyte sets ({} sets excluded by overlap limits)",
+                significant.len(),
+                excluded.len()
             );
+            save_redundancy(&significant, &gmt, &gsea_args.redundancy);
         }
         Some(Commands::Ora(ora_args)) => {
             check_and_overwrite(&ora_args.output);
+            if let Some(path) = &ora_args.excluded_output {
+                check_and_overwrite(path);
+            }
             let start = Instant::now();
             let (gmt, interest, reference) = webgestalt_lib::readers::read_ora_files(
                 ora_args.gmt.clone(),
@@ -274,27 +550,44 @@ fn main() {
             );
             println!("Reading Took {:?}", start.elapsed());
             let start = Instant::now();
-            let res = webgestalt_lib::methods::ora::get_ora(
+            let direction = match ora_args.direction {
+                ORADirectionClap::Over => ORATestDirection::Over,
+                ORADirectionClap::Under => ORATestDirection::Under,
+                ORADirectionClap::TwoSided => ORATestDirection::TwoSided,
+            };
+            let (res, excluded) = webgestalt_lib::methods::ora::get_ora_with_excluded(
                 &interest,
                 &reference,
-                gmt,
-                ORAConfig::default(),
+                gmt.clone(),
+                ORAConfig {
+                    report_overlap_ids: !ora_args.compact,
+                    min_overlap: ora_args
+                        .min_overlap
+                        .unwrap_or(ORAConfig::for_direction(direction).min_overlap),
+                    ..ORAConfig::for_direction(direction)
+                },
             );
             let output_file =
                 File::create(&ora_args.output).expect("Could not create output file!");
             serde_json::to_writer(output_file, &res).expect("Could not create JSON file!");
-            println!("Analysis Took {:?}", start.elapsed());
-            let mut count = 0;
-            for row in res.iter() {
-                if row.p < 0.05 && row.fdr < 0.05 {
-                    count += 1;
-                }
+            if let Some(path) = &ora_args.excluded_output {
+                webgestalt_lib::writers::save_ora_excluded(path.clone(), &excluded)
+                    .expect("Could not create excluded sets file!");
+                println!("Saved {} excluded sets to {}", excluded.len(), path);
             }
+            println!("Analysis Took {:?}", start.elapsed());
+            let significant: Vec<ORAResult> = res
+                .iter()
+                .filter(|row| row.p < 0.05 && row.fdr < 0.05)
+                .cloned()
+                .collect();
             println!(
-                "Found {} significant analyte sets out of {} sets",
-                count,
-                res.len()
+                "Found {} significant analyte sets out of {} sets ({} sets excluded by filters)",
+                significant.len(),
+                res.len(),
+                excluded.len()
             );
+            save_redundancy(&significant, &gmt, &ora_args.redundancy);
         }
         Some(Commands::Nta(nta_args)) => {
             check_and_overwrite(&nta_args.output);
@@ -314,10 +607,38 @@ fn main() {
                 reset_probability: nta_args.reset_probability,
                 tolerance: nta_args.tolerance,
                 method: Some(nta_method),
+                directed: nta_args.directed,
             };
-            let res = webgestalt_lib::methods::nta::get_nta(config);
-            println!("Analysis Took {:?}", start.elapsed());
-            webgestalt_lib::writers::save_nta(nta_args.output.clone(), res).unwrap();
+            match &nta_args.gmt {
+                Some(gmt_path) => {
+                    let gmt = read_gmt_file(gmt_path.clone()).unwrap_or_else(|_| {
+                        panic!("File {} not found", gmt_path);
+                    });
+                    let res = webgestalt_lib::methods::nta::get_nta_enrichment(
+                        config,
+                        gmt,
+                        ORAConfig::default(),
+                    );
+                    println!("Analysis Took {:?}", start.elapsed());
+                    let significant = res
+                        .enrichment
+                        .iter()
+                        .filter(|row| row.p < 0.05 && row.fdr < 0.05)
+                        .count();
+                    println!(
+                        "Found {} significant analyte sets in the neighborhood of {} nodes",
+                        significant,
+                        res.network.neighborhood.len()
+                    );
+                    webgestalt_lib::writers::save_nta_enrichment(nta_args.output.clone(), &res)
+                        .unwrap();
+                }
+                None => {
+                    let res = webgestalt_lib::methods::nta::get_nta(config);
+                    println!("Analysis Took {:?}", start.elapsed());
+                    webgestalt_lib::writers::save_nta(nta_args.output.clone(), res).unwrap();
+                }
+            }
         }
         Some(Commands::Combine(args)) => match &args.combine_type {
             Some(CombineType::Gmt(gmt_args)) => {
@@ -373,7 +694,7 @@ fn main() {
                 };
                 let mut combined_list =
                     webgestalt_lib::methods::multilist::combine_lists(lists, method);
-                combined_list.sort_by(|a, b| b.rank.partial_cmp(&a.rank).unwrap());
+                combined_list.sort_by(|a, b| b.rank.total_cmp(&a.rank));
                 let mut file = File::create(ora_args.out.clone().unwrap()).unwrap();
                 println!(
                     "{}: CREATING COMBINED LIST AT {}",
@@ -63,6 +63,8 @@ impl CustomError for MalformedError {
 pub enum StatisticsError {
     FoundNANValue,
     InvalidValue { value: f64 },
+    DuplicateAnalyte { analyte: String },
+    NonFiniteValue { analyte: String, value: f64 },
 }
 
 impl CustomError for StatisticsError {
@@ -70,6 +72,12 @@ impl CustomError for StatisticsError {
         let error_msg = match &self {
             StatisticsError::FoundNANValue => String::from("Found a NAN value"),
             StatisticsError::InvalidValue { value } => format!("Found invalid value: {}", value),
+            StatisticsError::DuplicateAnalyte { analyte } => {
+                format!("Found duplicate analyte: {}", analyte)
+            }
+            StatisticsError::NonFiniteValue { analyte, value } => {
+                format!("Found non-finite value {} for analyte {}", value, analyte)
+            }
         };
         format!("Statstical Error: {}.", error_msg)
     }
@@ -1,4 +1,10 @@
+pub mod competitive;
+pub mod goseq;
 pub mod gsea;
+pub mod gsva;
+pub mod mhg;
 pub mod multilist;
 pub mod nta;
+pub mod ontology;
 pub mod ora;
+pub mod redundancy;
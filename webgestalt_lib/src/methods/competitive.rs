@@ -0,0 +1,328 @@
+use crate::{
+    readers::utils::{ExpressionMatrix, Item, Phenotype},
+    stat,
+};
+use ahash::{AHashMap, AHashSet};
+use rayon::prelude::*;
+use serde::Serialize;
+use statrs::distribution::{ContinuousCDF, Normal, StudentsT};
+
+use super::gsea::RankListItem;
+
+/// Competitive test comparing the scores of the set analytes to the other analytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompetitiveMethod {
+    /// Parametric analysis of gene set enrichment, a z-score of the mean set score
+    /// ([DOI:10.1186/1471-2105-6-144](https://doi.org/10.1186/1471-2105-6-144))
+    PAGE,
+    /// Wilcoxon rank-sum (mean-rank) test of the set scores against the other scores, with a
+    /// normal approximation corrected for ties
+    Wilcoxon,
+    /// Correlation adjusted mean rank test of limma
+    /// ([DOI:10.1093/nar/gks461](https://doi.org/10.1093/nar/gks461)), a two-sample t-test with
+    /// the variance of the set mean inflated by the correlation between set analytes
+    Camera {
+        /// Correlation between the analytes of a set. If `None`, the correlation of every set is
+        /// estimated from the residuals of the expression matrix given to [`get_competitive`]
+        inter_gene_cor: Option<f64>,
+    },
+}
+
+/// Parameters for the competitive set tests
+#[derive(Clone)]
+pub struct CompetitiveConfig {
+    /// The [`CompetitiveMethod`] of the test
+    pub method: CompetitiveMethod,
+    /// Minimum number of set analytes in the rank list for the set to be tested
+    pub min_set_size: usize,
+    /// Maximum number of set analytes in the rank list for the set to be tested
+    pub max_set_size: usize,
+    pub fdr_method: stat::AdjustmentMethod,
+}
+
+impl Default for CompetitiveConfig {
+    fn default() -> Self {
+        CompetitiveConfig {
+            method: CompetitiveMethod::PAGE,
+            min_set_size: 5,
+            max_set_size: 500,
+            fdr_method: stat::AdjustmentMethod::BH,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CompetitiveResult {
+    /// The set name
+    pub set: String,
+    /// The two-sided p-value
+    pub p: f64,
+    /// The FDR value
+    pub fdr: f64,
+    /// The test statistic: a z-score for PAGE and Wilcoxon, and a t-statistic for CAMERA.
+    /// Positive values mean the set scores are higher than the other scores
+    pub statistic: f64,
+    /// Effect size: the standardized mean difference for PAGE and CAMERA, and the rank-biserial
+    /// correlation (`2 * AUC - 1`) for Wilcoxon
+    pub effect_size: f64,
+    /// The inter-gene correlation used by CAMERA. `0` for the other methods
+    pub correlation: f64,
+    /// Number of set analytes in the rank list
+    pub set_size: usize,
+}
+
+/// Run a fast competitive set test on a rank list. Unlike GSEA, no permutations are needed.
+///
+/// # Parameters
+///
+/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list. Analytes should be unique, see
+///   [`super::gsea::prepare::prepare_rank_list`]. Analytes with NaN or infinite ranks are left out
+/// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - [`CompetitiveConfig`] of the analysis
+/// - `expression` - Optional [`ExpressionMatrix`] and [`Phenotype`] of the samples the rank list
+///   was computed from. Only used by CAMERA to estimate the inter-gene correlation
+///
+/// # Panics
+///
+/// Panics if CAMERA should estimate the correlation but no expression matrix is provided.
+///
+/// # Returns
+///
+/// Returns a [`Vec<CompetitiveResult>`] of the sets within the size limits
+pub fn get_competitive(
+    analyte_list: Vec<RankListItem>,
+    gmt: Vec<Item>,
+    config: CompetitiveConfig,
+    expression: Option<(&ExpressionMatrix, &Phenotype)>,
+) -> Vec<CompetitiveResult> {
+    let analyte_list: Vec<RankListItem> = analyte_list
+        .into_iter()
+        .filter(|x| x.rank.is_finite())
+        .collect();
+    let (analytes, scores) = RankListItem::to_vecs(analyte_list);
+    let index: AHashMap<&String, usize> =
+        analytes.iter().enumerate().map(|(i, x)| (x, i)).collect();
+    let residuals = match config.method {
+        CompetitiveMethod::Camera {
+            inter_gene_cor: None,
+        } => {
+            let (matrix, phenotype) =
+                expression.expect("CAMERA needs an expression matrix to estimate correlation");
+            Some(normalized_residuals(matrix, phenotype))
+        }
+        _ => None,
+    };
+    // the ranks and their tie correction are the same for every set
+    let ranks = match config.method {
+        CompetitiveMethod::Wilcoxon => Some(average_ranks(&scores)),
+        _ => None,
+    };
+    let g = scores.len() as f64;
+    let mean = scores.iter().sum::<f64>() / g;
+    let variance = scores.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (g - 1.0);
+    let partials: Vec<CompetitiveResult> = gmt
+        .par_iter()
+        .filter_map(|item| {
+            let members: AHashSet<usize> = item
+                .parts
+                .iter()
+                .filter_map(|x| index.get(x).copied())
+                .collect();
+            let m = members.len();
+            if m < config.min_set_size.max(1) || m > config.max_set_size || m >= scores.len() {
+                return None;
+            }
+            let set_mean = members.iter().map(|i| scores[*i]).sum::<f64>() / m as f64;
+            let (p, statistic, effect_size, correlation) = match config.method {
+                CompetitiveMethod::PAGE => {
+                    let sd = variance.sqrt();
+                    let z = (set_mean - mean) * (m as f64).sqrt() / sd;
+                    (normal_two_sided(z), z, (set_mean - mean) / sd, 0.0)
+                }
+                CompetitiveMethod::Wilcoxon => {
+                    let (ranks, ties) = ranks.as_ref().unwrap();
+                    let (z, auc) = rank_sum(ranks, *ties, &members);
+                    (normal_two_sided(z), z, 2.0 * auc - 1.0, 0.0)
+                }
+                CompetitiveMethod::Camera { inter_gene_cor } => {
+                    let correlation = match inter_gene_cor {
+                        Some(x) => x,
+                        None => set_correlation(
+                            residuals.as_ref().unwrap(),
+                            members.iter().map(|i| &analytes[*i]),
+                        ),
+                    };
+                    let (t, effect_size) =
+                        camera_t(g, m as f64, mean, variance, set_mean, correlation);
+                    let t_dist = StudentsT::new(0.0, 1.0, g - 2.0).unwrap();
+                    let p = (2.0 * (1.0 - t_dist.cdf(t.abs()))).min(1.0);
+                    (p, t, effect_size, correlation)
+                }
+            };
+            Some(CompetitiveResult {
+                set: item.id.clone(),
+                p,
+                fdr: 1.0,
+                statistic,
+                effect_size,
+                correlation,
+                set_size: m,
+            })
+        })
+        .collect();
+    let p_vals: Vec<f64> = partials.iter().map(|x| x.p).collect();
+    let fdrs = stat::adjust(&p_vals, config.fdr_method);
+    partials
+        .into_iter()
+        .zip(fdrs)
+        .map(|(row, fdr)| CompetitiveResult { fdr, ..row })
+        .collect()
+}
+
+fn normal_two_sided(z: f64) -> f64 {
+    let normal = Normal::new(0.0, 1.0).unwrap();
+    (2.0 * (1.0 - normal.cdf(z.abs()))).min(1.0)
+}
+
+/// Ranks of the scores starting at 1, with the mean rank for ties.
+///
+/// Also returns the tie correction of the rank-sum variance, the sum of `t^3 - t` over the sizes
+/// `t` of the groups of tied scores
+fn average_ranks(scores: &[f64]) -> (Vec<f64>, f64) {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+    let mut ranks = vec![0.0; scores.len()];
+    let mut ties = 0.0;
+    let mut start = 0;
+    while start < order.len() {
+        let end = start
+            + order[start..]
+                .iter()
+                .take_while(|x| scores[**x] == scores[order[start]])
+                .count();
+        let rank = (start + end + 1) as f64 / 2.0;
+        for i in &order[start..end] {
+            ranks[*i] = rank;
+        }
+        let t = (end - start) as f64;
+        ties += t * t * t - t;
+        start = end;
+    }
+    (ranks, ties)
+}
+
+/// z-score with continuity correction and AUC of the Wilcoxon rank-sum test of the members
+/// against the other analytes. `ties` is the tie correction from [`average_ranks`]
+fn rank_sum(ranks: &[f64], ties: f64, members: &AHashSet<usize>) -> (f64, f64) {
+    let n = ranks.len() as f64;
+    let m = members.len() as f64;
+    let others = n - m;
+    let rank_sum: f64 = members.iter().map(|i| ranks[*i]).sum();
+    let u = rank_sum - m * (m + 1.0) / 2.0;
+    let expected = m * others / 2.0;
+    let variance = m * others / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    let difference = u - expected;
+    // no continuity correction for a difference of zero, as `signum` of zero is one
+    let corrected = if difference == 0.0 {
+        0.0
+    } else {
+        difference - 0.5 * difference.signum()
+    };
+    let z = if variance > 0.0 {
+        corrected / variance.sqrt()
+    } else {
+        0.0
+    };
+    (z, u / (m * others))
+}
+
+/// Two-sample t-statistic of CAMERA and the standardized mean difference
+fn camera_t(
+    g: f64,
+    m: f64,
+    mean: f64,
+    variance: f64,
+    set_mean: f64,
+    correlation: f64,
+) -> (f64, f64) {
+    let others = g - m;
+    let vif = 1.0 + (m - 1.0) * correlation;
+    // difference between the set mean and the mean of the other analytes
+    let delta = g / others * (set_mean - mean);
+    let pooled = ((g - 1.0) * variance - delta * delta * m * others / g) / (g - 2.0);
+    let t = delta / (pooled * (vif / m + 1.0 / others)).sqrt();
+    (t, delta / pooled.sqrt())
+}
+
+/// Residuals of every analyte after removing the phenotype effect, scaled to unit length
+fn normalized_residuals(
+    matrix: &ExpressionMatrix,
+    phenotype: &Phenotype,
+) -> AHashMap<String, Vec<f64>> {
+    matrix
+        .analytes
+        .par_iter()
+        .zip(matrix.values.par_iter())
+        .map(|(analyte, row)| {
+            let mut residual: Vec<f64> = match phenotype {
+                Phenotype::Categorical { classes, labels } => {
+                    let mut sums = vec![0.0; classes.len()];
+                    let mut counts = vec![0.0; classes.len()];
+                    for (x, label) in row.iter().zip(labels) {
+                        sums[*label] += x;
+                        counts[*label] += 1.0;
+                    }
+                    row.iter()
+                        .zip(labels)
+                        .map(|(x, label)| x - sums[*label] / counts[*label])
+                        .collect()
+                }
+                Phenotype::Continuous { values, .. } => {
+                    let n = row.len() as f64;
+                    let mean_x = values.iter().sum::<f64>() / n;
+                    let mean_y = row.iter().sum::<f64>() / n;
+                    let sxx: f64 = values.iter().map(|x| (x - mean_x).powi(2)).sum();
+                    let sxy: f64 = values
+                        .iter()
+                        .zip(row)
+                        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+                        .sum();
+                    let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+                    values
+                        .iter()
+                        .zip(row)
+                        .map(|(x, y)| y - mean_y - slope * (x - mean_x))
+                        .collect()
+                }
+            };
+            let length = residual.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if length > 0.0 {
+                residual.iter_mut().for_each(|x| *x /= length);
+            }
+            (analyte.clone(), residual)
+        })
+        .collect::<Vec<(String, Vec<f64>)>>()
+        .into_iter()
+        .collect()
+}
+
+/// Mean correlation between the residuals of the set analytes in the matrix
+fn set_correlation<'a>(
+    residuals: &AHashMap<String, Vec<f64>>,
+    members: impl Iterator<Item = &'a String>,
+) -> f64 {
+    let mut total: Vec<f64> = Vec::new();
+    let mut m = 0;
+    for residual in members.filter_map(|x| residuals.get(x)) {
+        if total.is_empty() {
+            total = vec![0.0; residual.len()];
+        }
+        total.iter_mut().zip(residual).for_each(|(a, b)| *a += b);
+        m += 1;
+    }
+    if m < 2 {
+        return 0.0;
+    }
+    let vif = total.iter().map(|x| x * x).sum::<f64>() / m as f64;
+    (vif - 1.0) / (m - 1) as f64
+}
@@ -0,0 +1,339 @@
+use crate::readers::utils::Item;
+use ahash::{AHashMap, AHashSet};
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+use super::ora::{
+    library::ORALibrary, ora_with_p_fn, ORAConfig, ORAExcludedSet, ORAResult, ORATestDirection,
+};
+
+/// Method to compute the bias-corrected p-values
+#[derive(Debug, Clone, Copy)]
+pub enum GOSeqMethod {
+    /// Wallenius noncentral hypergeometric distribution, with the odds ratio of each set given by
+    /// the mean selection weight of its analytes over the mean weight of the other analytes.
+    ///
+    /// The distribution of every set is computed exactly with [`wallenius_pmf`], which takes
+    /// `O(n * min(j, n))` time for an interest list of `n` analytes and a set of `j` analytes. For
+    /// interest lists of thousands of analytes, [`GOSeqMethod::Sampling`] is much faster.
+    Wallenius,
+    /// Sample random interest lists from the reference, with the probability of picking each
+    /// analyte given by its selection weight
+    Sampling {
+        /// Number of random interest lists
+        repeats: usize,
+        /// Seed of the random number generator
+        seed: u64,
+    },
+}
+
+/// Parameters for bias-corrected ORA
+#[derive(Clone)]
+pub struct GOSeqConfig {
+    /// The [`ORAConfig`] used for the set filters, test direction, and FDR
+    pub ora: ORAConfig,
+    /// The [`GOSeqMethod`] used to compute p-values
+    pub method: GOSeqMethod,
+    /// Number of analytes in each covariate bin when fitting the probability weighting function
+    pub bin_size: usize,
+}
+
+impl Default for GOSeqConfig {
+    fn default() -> Self {
+        GOSeqConfig {
+            ora: ORAConfig::default(),
+            method: GOSeqMethod::Wallenius,
+            bin_size: 500,
+        }
+    }
+}
+
+/// Fit the probability weighting function (PWF), the probability of an analyte being in the
+/// interest list given its covariate.
+///
+/// Reference analytes are sorted by covariate and split into bins of `bin_size`. The proportion of
+/// interesting analytes in each bin is smoothed with a monotone increasing (isotonic) regression,
+/// similar to the monotone spline of [goseq](https://doi.org/10.1186/gb-2010-11-2-r14). Bins
+/// without any interesting analyte get half an analyte so no weight is zero. Analytes without a
+/// covariate get the overall proportion of interesting analytes.
+///
+/// # Parameters
+///
+/// - `interest_list` - A [`AHashSet<String>`] of the interesting analytes
+/// - `reference` - A [`AHashSet<String>`] of the reference list
+/// - `covariate` - A [`AHashMap<String, f64>`] of the covariate (such as gene length) of each analyte
+/// - `bin_size` - Number of analytes in each bin
+///
+/// # Returns
+///
+/// Returns a [`AHashMap<String, f64>`] of the selection weight of every reference analyte
+pub fn probability_weighting(
+    interest_list: &AHashSet<String>,
+    reference: &AHashSet<String>,
+    covariate: &AHashMap<String, f64>,
+    bin_size: usize,
+) -> AHashMap<String, f64> {
+    let mut with_covariate: Vec<(&String, f64)> = reference
+        .iter()
+        .filter_map(|x| covariate.get(x).map(|c| (x, *c)))
+        .filter(|(_, c)| c.is_finite())
+        .collect();
+    with_covariate.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(b.0)));
+    let bins: Vec<&[(&String, f64)]> = with_covariate.chunks(bin_size.max(1)).collect();
+    let proportions: Vec<f64> = bins
+        .iter()
+        .map(|bin| {
+            let hits = bin
+                .iter()
+                .filter(|(x, _)| interest_list.contains(*x))
+                .count() as f64;
+            hits.max(0.5) / bin.len() as f64
+        })
+        .collect();
+    let sizes: Vec<f64> = bins.iter().map(|x| x.len() as f64).collect();
+    let fitted = isotonic_regression(&proportions, &sizes);
+    let overall = interest_list.len() as f64 / reference.len() as f64;
+    let mut weights: AHashMap<String, f64> =
+        reference.iter().map(|x| (x.clone(), overall)).collect();
+    for (bin, fit) in bins.iter().zip(fitted) {
+        for (analyte, _) in bin.iter() {
+            weights.insert((*analyte).clone(), fit);
+        }
+    }
+    weights
+}
+
+/// Weighted pool adjacent violators algorithm for a monotone increasing fit
+fn isotonic_regression(values: &[f64], weights: &[f64]) -> Vec<f64> {
+    // each block is (mean, weight, count)
+    let mut blocks: Vec<(f64, f64, usize)> = Vec::new();
+    for (value, weight) in values.iter().zip(weights) {
+        blocks.push((*value, *weight, 1));
+        while blocks.len() > 1 && blocks[blocks.len() - 2].0 > blocks[blocks.len() - 1].0 {
+            let (v2, w2, c2) = blocks.pop().unwrap();
+            let (v1, w1, c1) = blocks.pop().unwrap();
+            blocks.push(((v1 * w1 + v2 * w2) / (w1 + w2), w1 + w2, c1 + c2));
+        }
+    }
+    blocks
+        .into_iter()
+        .flat_map(|(value, _, count)| std::iter::repeat(value).take(count))
+        .collect()
+}
+
+/// Run ORA corrected for a selection bias of the interest list, such as the gene length bias of
+/// RNA-seq differential expression.
+///
+/// # Parameters
+///
+/// - `interest_list` - A [`AHashSet<String>`] of the interesting analytes
+/// - `reference` - A [`AHashSet<String>`] of the reference list
+/// - `gmt` - A [`Vec<Item>`] of the gmt file
+/// - `covariate` - A [`AHashMap<String, f64>`] of the covariate (such as gene length) of each analyte
+/// - `config` - The [`GOSeqConfig`] of the analysis
+///
+/// # Returns
+///
+/// Returns a tuple of the [`Vec<ORAResult>`] of the tested sets and a [`Vec<ORAExcludedSet>`]
+/// of the sets that were filtered out before FDR adjustment
+pub fn get_goseq(
+    interest_list: &AHashSet<String>,
+    reference: &AHashSet<String>,
+    gmt: Vec<Item>,
+    covariate: &AHashMap<String, f64>,
+    config: GOSeqConfig,
+) -> (Vec<ORAResult>, Vec<ORAExcludedSet>) {
+    let pwf = probability_weighting(interest_list, reference, covariate, config.bin_size);
+    let n = interest_list.len() as i64;
+    let direction = config.ora.direction;
+    let library = ORALibrary::new(&gmt);
+    let interest_bits = library.bitset(interest_list);
+    let reference_bits = library.bitset(reference);
+    match config.method {
+        GOSeqMethod::Wallenius => {
+            let total_weight: f64 = pwf.values().sum();
+            ora_with_p_fn(
+                &library,
+                &interest_bits,
+                &reference_bits,
+                config.ora,
+                |_, set, j, k| {
+                    let set_weight: f64 = set
+                        .analytes
+                        .iter()
+                        .filter(|x| reference_bits.contains(**x))
+                        .map(|x| pwf[library.name(*x)])
+                        .sum();
+                    let others = reference.len() as f64 - j as f64;
+                    let omega = if others == 0.0 || set_weight == 0.0 {
+                        1.0
+                    } else {
+                        (set_weight / j as f64) / ((total_weight - set_weight) / others)
+                    };
+                    let probs = wallenius_pmf(j, reference.len() as i64 - j, n, omega);
+                    tail_p(&probs, k, direction)
+                },
+            )
+        }
+        GOSeqMethod::Sampling { repeats, seed } => {
+            let p_vals = sampling_p(
+                interest_list,
+                reference,
+                &gmt,
+                &pwf,
+                repeats,
+                seed,
+                direction,
+            );
+            ora_with_p_fn(
+                &library,
+                &interest_bits,
+                &reference_bits,
+                config.ora,
+                |index, _, _, _| p_vals[index],
+            )
+        }
+    }
+}
+
+/// Probability of every overlap `0..=min(j, n)` under the Wallenius noncentral hypergeometric
+/// distribution. Uses the urn model directly, drawing one analyte at a time, so the cost is
+/// `O(n * min(j, n))`.
+///
+/// # Parameters
+///
+/// - `j` - Number of analytes in the set
+/// - `others` - Number of analytes not in the set
+/// - `n` - Number of analytes drawn
+/// - `omega` - Odds ratio of drawing an analyte in the set
+pub fn wallenius_pmf(j: i64, others: i64, n: i64, omega: f64) -> Vec<f64> {
+    let max_x = j.min(n) as usize;
+    let mut probs = vec![0.0; max_x + 2];
+    let mut next = vec![0.0; max_x + 2];
+    probs[0] = 1.0;
+    for i in 0..n {
+        next.iter_mut().for_each(|x| *x = 0.0);
+        for x in 0..=(i as usize).min(max_x) {
+            let p = probs[x];
+            if p == 0.0 {
+                continue;
+            }
+            let set_left = (j - x as i64) as f64;
+            let others_left = (others - (i - x as i64)) as f64;
+            if others_left < 0.0 {
+                continue;
+            }
+            let denom = omega * set_left + others_left;
+            let p_set = if denom == 0.0 {
+                0.0
+            } else {
+                omega * set_left / denom
+            };
+            next[x + 1] += p * p_set;
+            next[x] += p * (1.0 - p_set);
+        }
+        std::mem::swap(&mut probs, &mut next);
+    }
+    probs.truncate(max_x + 1);
+    probs
+}
+
+fn tail_p(probs: &[f64], k: i64, direction: ORATestDirection) -> f64 {
+    let k = k as usize;
+    let upper: f64 = probs.iter().skip(k).sum();
+    let lower: f64 = probs.iter().take(k + 1).sum();
+    match direction {
+        ORATestDirection::Over => upper.min(1.0),
+        ORATestDirection::Under => lower.min(1.0),
+        ORATestDirection::TwoSided => (2.0 * upper.min(lower)).min(1.0),
+    }
+}
+
+/// p-values from weighted random sampling of interest lists. Each repeat picks the interest list
+/// with weighted sampling without replacement ([DOI:10.1016/j.ipl.2005.11.003](https://doi.org/10.1016/j.ipl.2005.11.003)).
+/// The p-values are `(count + 1) / (repeats + 1)` so they are never zero, and are returned in the
+/// order of the gmt.
+fn sampling_p(
+    interest_list: &AHashSet<String>,
+    reference: &AHashSet<String>,
+    gmt: &[Item],
+    pwf: &AHashMap<String, f64>,
+    repeats: usize,
+    seed: u64,
+    direction: ORATestDirection,
+) -> Vec<f64> {
+    let mut analytes: Vec<&String> = reference.iter().collect();
+    analytes.sort();
+    let index: AHashMap<&String, usize> =
+        analytes.iter().enumerate().map(|(i, x)| (*x, i)).collect();
+    let weights: Vec<f64> = analytes.iter().map(|x| pwf[*x]).collect();
+    let sets: Vec<Vec<usize>> = gmt
+        .iter()
+        .map(|item| {
+            item.parts
+                .iter()
+                .filter_map(|x| index.get(x).copied())
+                .collect()
+        })
+        .collect();
+    let observed: Vec<usize> = gmt
+        .iter()
+        .map(|item| {
+            item.parts
+                .iter()
+                .filter(|x| interest_list.contains(*x))
+                .count()
+        })
+        .collect();
+    let n = interest_list.len();
+    let (upper, lower) = (0..repeats)
+        .into_par_iter()
+        .map(|r| {
+            let mut rng = rand::rngs::SmallRng::seed_from_u64(seed.wrapping_add(r as u64));
+            let mut keys: Vec<(f64, usize)> = weights
+                .iter()
+                .enumerate()
+                .map(|(i, w)| (rng.gen::<f64>().ln() / w, i))
+                .collect();
+            keys.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+            let mut picked = vec![false; analytes.len()];
+            for (_, i) in keys.iter().take(n) {
+                picked[*i] = true;
+            }
+            let mut upper = vec![0_usize; sets.len()];
+            let mut lower = vec![0_usize; sets.len()];
+            for (s, set) in sets.iter().enumerate() {
+                let overlap = set.iter().filter(|x| picked[**x]).count();
+                if overlap >= observed[s] {
+                    upper[s] += 1;
+                }
+                if overlap <= observed[s] {
+                    lower[s] += 1;
+                }
+            }
+            (upper, lower)
+        })
+        .reduce(
+            || (vec![0; sets.len()], vec![0; sets.len()]),
+            |(mut u1, mut l1), (u2, l2)| {
+                for s in 0..u1.len() {
+                    u1[s] += u2[s];
+                    l1[s] += l2[s];
+                }
+                (u1, l1)
+            },
+        );
+    upper
+        .iter()
+        .zip(lower.iter())
+        .map(|(upper, lower)| {
+            let upper = (upper + 1) as f64 / (repeats + 1) as f64;
+            let lower = (lower + 1) as f64 / (repeats + 1) as f64;
+            match direction {
+                ORATestDirection::Over => upper,
+                ORATestDirection::Under => lower,
+                ORATestDirection::TwoSided => (2.0 * upper.min(lower)).min(1.0),
+            }
+        })
+        .collect()
+}
@@ -1,14 +1,19 @@
 use crate::readers::utils::Item;
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
 use rand::prelude::SliceRandom;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
 use serde::Serialize;
 
+pub mod multilevel;
+pub mod phenotype;
+pub mod prepare;
+
 /// Parameters for GSEA
 #[derive(Clone)]
 pub struct GSEAConfig {
-    /// Power to raise each rank during the enrichment scoring
+    /// Power to raise each rank during the enrichment scoring. See [`WeightingScheme`] for the
+    /// named values
     pub p: f64,
     /// Minimum overlap the analyte set must have to be included in the analysis
     pub min_overlap: i32,
@@ -16,6 +21,10 @@ pub struct GSEAConfig {
     pub max_overlap: i32,
     /// Number of permutations to use in the analysis
     pub permutations: i32,
+    /// Seed for the permutations. A random seed is used if `None`
+    pub seed: Option<u64>,
+    /// How much of the running sum to keep in each [`GSEAResult`]
+    pub running_sum: RunningSumStorage,
 }
 
 impl Default for GSEAConfig {
@@ -25,11 +34,154 @@ impl Default for GSEAConfig {
             min_overlap: 15,
             max_overlap: 500,
             permutations: 1000,
+            seed: None,
+            running_sum: RunningSumStorage::None,
         }
     }
 }
 
-#[derive(Clone)]
+impl GSEAConfig {
+    /// Check the overlap of a set with the rank list against the config filters. `set_size` is the
+    /// number of analytes in the set and `list_size` the length of the rank list. Returns the
+    /// [`GSEAExclusionReason`] if the set should not be scored.
+    pub fn exclusion_reason(
+        &self,
+        overlap: i32,
+        set_size: usize,
+        list_size: usize,
+    ) -> Option<GSEAExclusionReason> {
+        if overlap < self.min_overlap {
+            Some(GSEAExclusionReason::OverlapTooSmall)
+        } else if overlap > self.max_overlap {
+            Some(GSEAExclusionReason::OverlapTooLarge)
+        } else if set_size >= list_size {
+            Some(GSEAExclusionReason::SetLargerThanList)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reason an analyte set was left out of GSEA scoring and FDR calculation
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GSEAExclusionReason {
+    /// Fewer analytes of the set are in the rank list than [`GSEAConfig::min_overlap`]
+    OverlapTooSmall,
+    /// More analytes of the set are in the rank list than [`GSEAConfig::max_overlap`]
+    OverlapTooLarge,
+    /// The set has at least as many analytes as the rank list, so there are no analytes to
+    /// compare it against
+    SetLargerThanList,
+}
+
+/// An analyte set that was not scored, with the reason it was excluded
+#[derive(Debug, Serialize, Clone)]
+pub struct GSEAExcludedSet {
+    pub set: String,
+    /// Number of analytes of the set in the rank list
+    pub overlap: i32,
+    pub reason: GSEAExclusionReason,
+}
+
+/// How much of the running sum of every set to keep. The running sum is as long as the rank list,
+/// so keeping it in full makes large outputs. Use [`enrichment_plot_data`] to get the plotting
+/// data of selected sets instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunningSumStorage {
+    /// Do not keep the running sum
+    None,
+    /// Keep about this many points, including the minimum and maximum of evenly spaced windows
+    /// and the first and last points. The positions of the points are in
+    /// [`GSEAResult::running_sum_positions`]
+    Downsampled(usize),
+    /// Keep every point
+    Full,
+}
+
+impl RunningSumStorage {
+    /// Returns the kept values and their positions. Positions are empty if every point is kept
+    fn store(&self, running_sum: Vec<f64>) -> (Vec<f64>, Vec<usize>) {
+        match self {
+            RunningSumStorage::None => (Vec::new(), Vec::new()),
+            RunningSumStorage::Full => (running_sum, Vec::new()),
+            RunningSumStorage::Downsampled(points) => {
+                let n = running_sum.len();
+                if n == 0 {
+                    return (Vec::new(), Vec::new());
+                }
+                let windows = (points.saturating_sub(2) / 2).max(1);
+                let window_size = ((n + windows - 1) / windows).max(1);
+                let mut positions: Vec<usize> = vec![0];
+                for start in (0..n).step_by(window_size) {
+                    let end = (start + window_size).min(n);
+                    let (mut min, mut max) = (start, start);
+                    for i in start..end {
+                        if running_sum[i] < running_sum[min] {
+                            min = i;
+                        }
+                        if running_sum[i] > running_sum[max] {
+                            max = i;
+                        }
+                    }
+                    positions.push(min.min(max));
+                    positions.push(min.max(max));
+                }
+                positions.push(n - 1);
+                positions.dedup();
+                let values = positions.iter().map(|i| running_sum[*i]).collect();
+                (values, positions)
+            }
+        }
+    }
+}
+
+/// Named weighting schemes of the enrichment score, matching the Broad GSEA implementation
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightingScheme {
+    /// Unweighted Kolmogorov-Smirnov statistic (`p = 0`)
+    Classic,
+    /// Ranks are used as weights (`p = 1`)
+    Weighted,
+    /// Ranks are squared (`p = 2`)
+    WeightedP2,
+    /// Ranks are raised to the power of 1.5 (`p = 1.5`)
+    #[serde(rename = "weighted_p1.5")]
+    WeightedP1_5,
+    /// Any other power
+    Custom(f64),
+}
+
+impl WeightingScheme {
+    /// The power each rank is raised to
+    pub fn p(&self) -> f64 {
+        match self {
+            WeightingScheme::Classic => 0.0,
+            WeightingScheme::Weighted => 1.0,
+            WeightingScheme::WeightedP2 => 2.0,
+            WeightingScheme::WeightedP1_5 => 1.5,
+            WeightingScheme::Custom(p) => *p,
+        }
+    }
+
+    /// Get the named scheme for the power `p`, or [`WeightingScheme::Custom`]
+    pub fn from_p(p: f64) -> WeightingScheme {
+        if p == 0.0 {
+            WeightingScheme::Classic
+        } else if p == 1.0 {
+            WeightingScheme::Weighted
+        } else if p == 2.0 {
+            WeightingScheme::WeightedP2
+        } else if p == 1.5 {
+            WeightingScheme::WeightedP1_5
+        } else {
+            WeightingScheme::Custom(p)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RankListItem {
     pub analyte: String,
     pub rank: f64,
@@ -40,21 +192,52 @@ struct PartialGSEAResult {
     p: f64,
     es: f64,
     nes: f64,
-    leading_edge: i32,
+    leading_edge: LeadingEdge,
     running_sum: Vec<f64>,
+    running_sum_positions: Vec<usize>,
     nes_iter: Vec<f64>,
+    null_counts: NullCounts,
+}
+
+/// Counts of the null enrichment scores of one analyte set
+#[derive(Clone, Copy, Default)]
+struct NullCounts {
+    /// Number of null scores
+    permutations: usize,
+    /// Number of null scores with the same sign as the observed score
+    same_sign: usize,
+    /// Number of those at least as extreme as the observed score
+    exceedances: usize,
+}
+
+/// Leading edge of one analyte set
+#[derive(Clone, Default)]
+struct LeadingEdge {
+    analytes: Vec<String>,
+    rank_at_max: usize,
+    tags: f64,
+    list: f64,
+    signal: f64,
 }
 
 impl PartialGSEAResult {
-    pub fn add_fdr(&self, fdr: f64) -> GSEAResult {
+    pub fn add_fdr(&self, fdr: f64, scheme: WeightingScheme, seed: Option<u64>) -> GSEAResult {
         GSEAResult {
             set: self.set.clone(),
             p: self.p,
             fdr,
             es: self.es,
             nes: self.nes,
-            leading_edge: self.leading_edge,
+            leading_edge: self.leading_edge.analytes.len() as i32,
+            leading_edge_analytes: self.leading_edge.analytes.clone(),
+            rank_at_max: self.leading_edge.rank_at_max,
+            tags: self.leading_edge.tags,
+            list: self.leading_edge.list,
+            signal: self.leading_edge.signal,
             running_sum: self.running_sum.clone(),
+            running_sum_positions: self.running_sum_positions.clone(),
+            scheme,
+            seed,
         }
     }
 }
@@ -73,8 +256,25 @@ pub struct GSEAResult {
     pub nes: f64,
     /// Leading edge count
     pub leading_edge: i32,
-    /// Running sum vector
+    /// The analytes in the leading edge, in rank list order
+    pub leading_edge_analytes: Vec<String>,
+    /// Position (starting at 0) in the sorted rank list of the maximum enrichment score
+    pub rank_at_max: usize,
+    /// Fraction of the set analytes in the leading edge
+    pub tags: f64,
+    /// Fraction of the rank list before (or after, for negative scores) the maximum
+    pub list: f64,
+    /// Enrichment signal strength, `tags * (1 - list) * N / (N - Nh)`
+    pub signal: f64,
+    /// Running sum vector, as kept by [`GSEAConfig::running_sum`]
     pub running_sum: Vec<f64>,
+    /// Positions in the rank list of the `running_sum` values. Empty if `running_sum` is empty or
+    /// has every point
+    pub running_sum_positions: Vec<usize>,
+    /// The [`WeightingScheme`] used for the enrichment score
+    pub scheme: WeightingScheme,
+    /// The seed used for the permutations. `None` if the permutations were provided
+    pub seed: Option<u64>,
 }
 
 impl RankListItem {
@@ -87,11 +287,20 @@ impl RankListItem {
         }
         (analytes, ranks)
     }
+
+    /// Remove the analytes with NaN or infinite ranks, which can not be placed in the ranking, and
+    /// sort the others by decreasing rank. Use [`prepare::prepare_rank_list`] to report or reject
+    /// them instead.
+    pub(crate) fn sort_finite(list: &mut Vec<RankListItem>) {
+        list.retain(|x| x.rank.is_finite());
+        list.sort_by(|a, b| b.rank.total_cmp(&a.rank));
+    }
 }
 
 /// Run GSEA for one analyte set.
 ///
-/// Returns a [`GSEAResult`], which does not have FDR.
+/// Returns a [`PartialGSEAResult`], which does not have FDR, or the [`GSEAExcludedSet`] if the
+/// overlap is outside the limits of `config`.
 ///
 /// # Parameters
 ///
@@ -99,24 +308,21 @@ impl RankListItem {
 /// - `ranks` - Slice of [`f64`] containing the rank values corresponding to the `analytes` vector
 /// - `item` - [`Item`] of the analyte set
 /// - `p` - The power to raise the ranks. **Not the statistical p-value**
-/// - `permutations_vec` - Vector of Vectors of random permutations of [`usize`] that are indices
-/// used to shuffle analytes
+/// - `null` - [`NullSource`] of the permutations
 ///
 /// # Panics
 ///
 /// Panics if the `ranks` and `analytes` parameters are not the same length.
 fn analyte_set_p(
-    analytes: &Vec<String>,
+    analytes: &[String],
     ranks: &[f64],
     item: &Item,
     p: f64,
-    permutations_vec: &Vec<Vec<usize>>,
+    null: &NullSource,
     config: &GSEAConfig,
-) -> PartialGSEAResult {
-    let permutations = permutations_vec.len();
+) -> Result<PartialGSEAResult, GSEAExcludedSet> {
     let analyte_set = AHashSet::from_iter(item.parts.iter());
     let mut n_r: f64 = 0.0;
-    let inverse_size_dif: f64 = 1.0 / ((analytes.len() - analyte_set.len()) as f64); // Inverse now
     let analyte_count = analytes.len();
     let mut overlap: i32 = 0;
     for j in 0..analyte_count {
@@ -126,18 +332,15 @@ fn analyte_set_p(
             overlap += 1;
         }
     }
-    if overlap < config.min_overlap || overlap > config.max_overlap {
-        PartialGSEAResult {
-            // No GSEA needed
+    if let Some(reason) = config.exclusion_reason(overlap, analyte_set.len(), analyte_count) {
+        // No GSEA needed
+        Err(GSEAExcludedSet {
             set: item.id.clone(),
-            p: 1.0,
-            nes: 0.0,
-            es: 0.0,
-            leading_edge: 0,
-            running_sum: Vec::new(),
-            nes_iter: Vec::new(),
-        }
+            overlap,
+            reason,
+        })
     } else {
+        let inverse_size_dif: f64 = 1.0 / ((analyte_count - analyte_set.len()) as f64); // Inverse now
         let inverse_nr = 1.0 / n_r; // Invert n_r for the enrichment score
         let original_order = (0..analyte_count).collect::<Vec<usize>>(); // get regular order
         let has_analyte: Vec<bool> = analytes.iter().map(|x| analyte_set.contains(x)).collect(); // create vector of booleans where
@@ -148,8 +351,8 @@ fn analyte_set_p(
         } else {
             ranks.par_iter().map(|x| x.abs()).collect()
         };
-        let (real_es, max_hits, running_sum) = enrichment_score(
-            // get normal es and leading edge max_hits
+        let (real_es, max_index, running_sum) = enrichment_score(
+            // get normal es and the position of the maximum
             &has_analyte,
             &new_ranks,
             &original_order,
@@ -157,98 +360,220 @@ fn analyte_set_p(
             inverse_nr,
             false,
         );
-        let es_iter: Vec<f64> = (0..permutations)
-            .into_par_iter()
-            .map(|i| {
-                // get es for the permutations
-                let (p_es, _, _) = enrichment_score(
-                    &has_analyte,
-                    &new_ranks,
-                    &permutations_vec[i],
-                    inverse_size_dif,
-                    inverse_nr,
-                    true,
-                );
-                p_es
-            })
-            .collect();
-        let side: Vec<&f64> = if real_es >= 0_f64 {
-            // get side of distribution for p value
-            es_iter.iter().filter(|x| *x >= &0_f64).collect()
-        } else {
-            es_iter.iter().filter(|x| *x < &0_f64).collect()
+        let es_iter: Vec<f64> = match null {
+            NullSource::Seeded { seed, permutations } => (0..*permutations)
+                .into_par_iter()
+                .map(|i| {
+                    // only the positions of the hits matter, so sample them directly
+                    let mut rng = rand::rngs::SmallRng::seed_from_u64(seed.wrapping_add(i as u64));
+                    let positions = sample_positions(&mut rng, analyte_count, overlap as usize);
+                    random_set_es(&positions, &new_ranks, inverse_size_dif)
+                })
+                .collect(),
+            NullSource::Provided(permutations_vec) => permutations_vec
+                .par_iter()
+                .map(|permutation| {
+                    // get es for the permutations
+                    let (p_es, _, _) = enrichment_score(
+                        &has_analyte,
+                        &new_ranks,
+                        permutation,
+                        inverse_size_dif,
+                        inverse_nr,
+                        true,
+                    );
+                    p_es
+                })
+                .collect(),
         };
-        let tot = side.len();
-        let p: f64 = if tot != 0 {
-            // calculate p value
-            side.into_iter()
-                .filter(|x| x.abs() >= real_es.abs())
-                .count() as f64
-                / tot as f64
-        } else {
-            // no higher values found, so p is '0.0'. Previously < 2.2e-16 on the R version
-            0.0
-        };
-        let up: Vec<f64> = es_iter // get positive (up) ES
-            .par_iter()
-            .filter(|&x| *x >= 0_f64)
-            .copied()
-            .collect();
-        let down: Vec<f64> = es_iter // down scores
-            .par_iter()
-            .filter(|&x| *x <= 0_f64)
-            .copied()
-            .collect();
-        let up_len = up.len();
-        let down_len = down.len();
-        let up_avg: f64 = if up.is_empty() {
-            0.000001
-        } else {
-            up.par_iter().sum::<f64>() / (up_len as f64 + 0.000001) + 0.000001
-        }; // up average
-        let down_avg: f64 = if down.is_empty() {
-            -0.000001
-        } else {
-            down.par_iter().sum::<f64>() / (down_len as f64 - 0.000001) - 0.000001
-        }; // down average
-        let mut nes_es: Vec<f64> = up.par_iter().map(|x| x / up_avg).collect(); // get all normalized scores for up
-        nes_es.extend(down.par_iter().map(|x| -x / down_avg).collect::<Vec<f64>>()); // extend with down scores
-        let norm_es: f64 = if real_es >= 0_f64 {
-            // get normalized score for the real run
-            if up.is_empty() {
-                0.0
-            } else {
-                real_es / up_avg
-            }
-        } else if down.is_empty() {
-            0.0
-        } else {
-            -real_es / down_avg
-        };
-        PartialGSEAResult {
+        let (p, norm_es, nes_es, null_counts) = null_statistics(real_es, &es_iter);
+        let (running_sum, running_sum_positions) = config.running_sum.store(running_sum);
+        Ok(PartialGSEAResult {
             set: item.id.clone(),
             p,
             nes: norm_es,
             es: real_es,
-            leading_edge: max_hits,
+            leading_edge: leading_edge(analytes, &has_analyte, real_es, max_index, overlap),
             running_sum,
+            running_sum_positions,
             nes_iter: nes_es,
+            null_counts,
+        })
+    }
+}
+
+/// Calculates the p-value and normalized enrichment score of a set from its null distribution of
+/// enrichment scores.
+///
+/// Returns the p-value, the normalized enrichment score, the normalized null scores, and the
+/// [`NullCounts`] behind the p-value
+fn null_statistics(real_es: f64, es_iter: &[f64]) -> (f64, f64, Vec<f64>, NullCounts) {
+    let side: Vec<&f64> = if real_es >= 0_f64 {
+        // get side of distribution for p value
+        es_iter.iter().filter(|x| *x >= &0_f64).collect()
+    } else {
+        es_iter.iter().filter(|x| *x < &0_f64).collect()
+    };
+    let tot = side.len();
+    let exceedances = side
+        .into_iter()
+        .filter(|x| x.abs() >= real_es.abs())
+        .count();
+    let p: f64 = if tot != 0 {
+        // calculate p value
+        exceedances as f64 / tot as f64
+    } else {
+        // no higher values found, so p is '0.0'. Previously < 2.2e-16 on the R version
+        0.0
+    };
+    let up: Vec<f64> = es_iter // get positive (up) ES
+        .par_iter()
+        .filter(|&x| *x >= 0_f64)
+        .copied()
+        .collect();
+    let down: Vec<f64> = es_iter // down scores
+        .par_iter()
+        .filter(|&x| *x <= 0_f64)
+        .copied()
+        .collect();
+    let up_len = up.len();
+    let down_len = down.len();
+    let up_avg: f64 = if up.is_empty() {
+        0.000001
+    } else {
+        // sequential sums so the result does not depend on the number of threads
+        up.iter().sum::<f64>() / (up_len as f64 + 0.000001) + 0.000001
+    }; // up average
+    let down_avg: f64 = if down.is_empty() {
+        -0.000001
+    } else {
+        down.iter().sum::<f64>() / (down_len as f64 - 0.000001) - 0.000001
+    }; // down average
+    let mut nes_es: Vec<f64> = up.par_iter().map(|x| x / up_avg).collect(); // get all normalized scores for up
+    nes_es.extend(down.par_iter().map(|x| -x / down_avg).collect::<Vec<f64>>()); // extend with down scores
+    let norm_es: f64 = if real_es >= 0_f64 {
+        // get normalized score for the real run
+        if up.is_empty() {
+            0.0
+        } else {
+            real_es / up_avg
         }
+    } else if down.is_empty() {
+        0.0
+    } else {
+        -real_es / down_avg
+    };
+    let counts = NullCounts {
+        permutations: es_iter.len(),
+        same_sign: tot,
+        exceedances,
+    };
+    (p, norm_es, nes_es, counts)
+}
+
+/// Source of the null enrichment scores of analyte permutations
+enum NullSource {
+    /// Sample the hit positions of every permutation from a seeded stream, so memory is
+    /// `O(set size)` and each permutation takes `O(k log k)`. Permutation `i` uses the seed
+    /// `seed + i`, so sets of the same size share their permutations
+    Seeded { seed: u64, permutations: usize },
+    /// Permutations of the whole rank list provided by the caller
+    Provided(Vec<Vec<usize>>),
+}
+
+/// Sample `amount` distinct positions out of `length` with Floyd's algorithm, sorted. Takes
+/// `O(amount log amount)` time and `O(amount)` memory however long the list is.
+fn sample_positions<R: Rng>(rng: &mut R, length: usize, amount: usize) -> Vec<usize> {
+    let mut picked: AHashSet<usize> = AHashSet::with_capacity(amount);
+    for j in (length - amount)..length {
+        let t = rng.gen_range(0..=j);
+        if !picked.insert(t) {
+            picked.insert(j);
+        }
+    }
+    let mut positions: Vec<usize> = picked.into_iter().collect();
+    positions.sort_unstable();
+    positions
+}
+
+/// Enrichment score of a set given only the sorted positions of its analytes in the rank list.
+/// Between hits the running sum only decreases, so the maximum deviation is right before or
+/// after a hit, or at the end of the list.
+///
+/// # Parameters
+///
+/// - `positions` - Sorted positions of the set analytes in the rank list
+/// - `ranks` - Weighted ranks of the whole list, already raised to the power `p`
+/// - `inverse_size_dif` - Decrease of the running sum for every miss
+fn random_set_es(positions: &[usize], ranks: &[f64], inverse_size_dif: f64) -> f64 {
+    let n_r: f64 = positions.iter().map(|x| ranks[*x]).sum();
+    let mut max_score: f64 = 0.0;
+    let mut sum_hits = 0.0;
+    for (i, position) in positions.iter().enumerate() {
+        let misses = (position - i) as f64 * inverse_size_dif;
+        let before = sum_hits / n_r - misses;
+        if before.abs() > max_score.abs() {
+            max_score = before;
+        }
+        sum_hits += ranks[*position];
+        let after = sum_hits / n_r - misses;
+        if after.abs() > max_score.abs() {
+            max_score = after;
+        }
+    }
+    let end = 1.0 - (ranks.len() - positions.len()) as f64 * inverse_size_dif;
+    if end.abs() > max_score.abs() {
+        max_score = end;
+    }
+    max_score
+}
+
+/// Find the leading edge analytes and the Broad GSEA leading edge statistics
+fn leading_edge(
+    analytes: &[String],
+    has_analyte: &[bool],
+    es: f64,
+    max_index: usize,
+    overlap: i32,
+) -> LeadingEdge {
+    let n = analytes.len();
+    if es == 0.0 || n == 0 {
+        return LeadingEdge::default();
+    }
+    let range = if es > 0.0 {
+        0..max_index + 1
+    } else {
+        max_index..n
+    };
+    let list = range.len() as f64 / n as f64;
+    let le_analytes: Vec<String> = range
+        .filter(|i| has_analyte[*i])
+        .map(|i| analytes[i].clone())
+        .collect();
+    let tags = le_analytes.len() as f64 / overlap as f64;
+    let signal = tags * (1.0 - list) * n as f64 / (n as f64 - overlap as f64);
+    LeadingEdge {
+        analytes: le_analytes,
+        rank_at_max: max_index,
+        tags,
+        list,
+        signal,
     }
 }
 
 /// Calculates the enrichment score for the specified list.
+///
+/// Returns the enrichment score, the position of the maximum, and the running sum
 fn enrichment_score(
-    analytes: &Vec<bool>,
+    analytes: &[bool],
     ranks: &[f64],
     order: &[usize],
     inverse_size_dif: f64,
     inverse_nr: f64,
     is_perm: bool,
-) -> (f64, i32, Vec<f64>) {
+) -> (f64, usize, Vec<f64>) {
     let mut max_score: f64 = 0.0;
-    let mut hits: i32 = 0;
-    let mut max_hits: i32 = 0;
+    let mut max_index: usize = 0;
     let mut sum_hits: f64 = 0.0;
     let mut sum_miss: f64 = 0.0;
     let mut running_sum: Vec<f64> = Vec::new();
@@ -269,7 +594,6 @@ fn enrichment_score(
         if analytes[order[i]] {
             // found in gene set
             sum_hits += ranks[i];
-            hits += 1;
         } else {
             // not in set
             sum_miss += 1.0;
@@ -281,117 +605,322 @@ fn enrichment_score(
         if es.abs() > max_score.abs() {
             // if bigger deviation from zero, store
             max_score = es;
-            max_hits = hits;
+            max_index = i;
         }
     }
-    (
-        max_score,
-        if max_score > 0.0 {
-            max_hits
-        } else {
-            running_sum.len() as i32 - max_hits
-        },
-        running_sum,
-    )
+    (max_score, max_index, running_sum)
 }
 
 /// Run GSEA and return a [`Vec<FullGSEAResult>`] for all analayte sets.
 ///
+/// Sets with an overlap outside the limits of `config` are not scored and are not counted in the
+/// FDR calculation. Use [`gsea_with_excluded`] to also get the excluded sets.
+///
 /// # Parameters
 ///
-/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list
+/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list. Analytes with NaN or infinite ranks
+///   are left out
 /// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - [`GSEAConfig`] of the analysis
+/// - `provided_permutations` - Optional permutations of the whole rank list to use instead of the
+///   sampled null
 ///
 /// # Returns
 ///
 /// Returns a [`Vec<FullGSEAResult>`] of the GSEA results
 pub fn gsea(
-    mut analyte_list: Vec<RankListItem>,
+    analyte_list: Vec<RankListItem>,
     gmt: Vec<Item>,
     config: GSEAConfig,
     provided_permutations: Option<Vec<Vec<usize>>>,
 ) -> Vec<GSEAResult> {
+    gsea_with_excluded(analyte_list, gmt, config, provided_permutations).0
+}
+
+/// Run GSEA and also return the sets excluded by the overlap limits of `config`.
+///
+/// # Parameters
+///
+/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list. Analytes with NaN or infinite ranks
+///   are left out
+/// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - [`GSEAConfig`] of the analysis
+/// - `provided_permutations` - Optional permutations of the whole rank list to use instead of the
+///   sampled null
+///
+/// # Returns
+///
+/// Returns a tuple of the [`Vec<GSEAResult>`] of the scored sets and a [`Vec<GSEAExcludedSet>`]
+/// of the sets that were left out before scoring
+pub fn gsea_with_excluded(
+    mut analyte_list: Vec<RankListItem>,
+    gmt: Vec<Item>,
+    config: GSEAConfig,
+    provided_permutations: Option<Vec<Vec<usize>>>,
+) -> (Vec<GSEAResult>, Vec<GSEAExcludedSet>) {
     println!("Starting GSEA Calculation.");
-    analyte_list.sort_by(|a, b| b.rank.partial_cmp(&a.rank).unwrap()); // sort list
+    RankListItem::sort_finite(&mut analyte_list);
     let (analytes, ranks) = RankListItem::to_vecs(analyte_list.clone()); // seperate into vectors
-    let permutations: Vec<Vec<usize>> =
-        provided_permutations.unwrap_or(make_permutations(config.permutations, analytes.len()));
-    let partial_results: Vec<PartialGSEAResult> = gmt
+    let (partial_results, excluded, seed, _) =
+        score_sets(&analytes, &ranks, &gmt, &config, provided_permutations);
+    (
+        add_fdrs(&partial_results, WeightingScheme::from_p(config.p), seed),
+        excluded,
+    )
+}
+
+/// Data to draw the enrichment plots of selected analyte sets
+#[derive(Debug, Serialize, Clone)]
+pub struct EnrichmentPlotData {
+    /// Analytes of the rank list, sorted by rank
+    pub analytes: Vec<String>,
+    /// Rank metric of every analyte, in the same order as `analytes`
+    pub ranks: Vec<f64>,
+    /// Plotting data of every selected set
+    pub sets: Vec<SetPlotData>,
+}
+
+/// Enrichment plot data of one analyte set
+#[derive(Debug, Serialize, Clone)]
+pub struct SetPlotData {
+    /// The set name
+    pub set: String,
+    /// The enrichment score
+    pub es: f64,
+    /// Positions in the sorted rank list of the set analytes
+    pub hit_positions: Vec<usize>,
+    /// Full running sum of the enrichment score
+    pub running_sum: Vec<f64>,
+}
+
+/// Get the data to draw enrichment plots of selected sets, with the same enrichment score as
+/// [`gsea`]. Selected sets are plotted even if they are outside the overlap limits, and names
+/// missing from `gmt` are skipped.
+///
+/// # Parameters
+///
+/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list. Analytes with NaN or infinite ranks
+///   are left out
+/// - `gmt` - Slice of [`Item`] of the gmt file
+/// - `sets` - Names of the sets to plot
+/// - `config` - [`GSEAConfig`] of the analysis. Only the weight `p` is used
+///
+/// # Returns
+///
+/// Returns the [`EnrichmentPlotData`] of the selected sets, in the order of `sets`
+pub fn enrichment_plot_data(
+    mut analyte_list: Vec<RankListItem>,
+    gmt: &[Item],
+    sets: &[String],
+    config: &GSEAConfig,
+) -> EnrichmentPlotData {
+    RankListItem::sort_finite(&mut analyte_list);
+    let (analytes, ranks) = RankListItem::to_vecs(analyte_list);
+    let weighted_ranks: Vec<f64> = ranks.iter().map(|x| x.abs().powf(config.p)).collect();
+    let order: Vec<usize> = (0..analytes.len()).collect();
+    let by_name: AHashMap<&str, &Item> = gmt.iter().map(|x| (x.id.as_str(), x)).collect();
+    let sets = sets
+        .par_iter()
+        .filter_map(|name| by_name.get(name.as_str()))
+        .map(|item| {
+            let analyte_set: AHashSet<&String> = item.parts.iter().collect();
+            let has_analyte: Vec<bool> = analytes.iter().map(|x| analyte_set.contains(x)).collect();
+            let hit_positions: Vec<usize> =
+                (0..analytes.len()).filter(|i| has_analyte[*i]).collect();
+            let n_r: f64 = hit_positions.iter().map(|i| weighted_ranks[*i]).sum();
+            let inverse_size_dif = 1.0 / (analytes.len().saturating_sub(analyte_set.len()) as f64);
+            let (es, _, running_sum) = enrichment_score(
+                &has_analyte,
+                &weighted_ranks,
+                &order,
+                inverse_size_dif,
+                1.0 / n_r,
+                false,
+            );
+            SetPlotData {
+                set: item.id.clone(),
+                es,
+                hit_positions,
+                running_sum,
+            }
+        })
+        .collect();
+    EnrichmentPlotData {
+        analytes,
+        ranks,
+        sets,
+    }
+}
+
+/// Score every set of the sorted rank list against analyte permutations.
+///
+/// Returns the [`PartialGSEAResult`] of every scored set, the [`GSEAExcludedSet`] of every set
+/// outside the overlap limits, the seed of the permutations, which is `None` if the
+/// permutations were provided, and the index in `gmt` of every scored set
+fn score_sets(
+    analytes: &[String],
+    ranks: &[f64],
+    gmt: &[Item],
+    config: &GSEAConfig,
+    provided_permutations: Option<Vec<Vec<usize>>>,
+) -> (
+    Vec<PartialGSEAResult>,
+    Vec<GSEAExcludedSet>,
+    Option<u64>,
+    Vec<usize>,
+) {
+    let (null, seed) = match provided_permutations {
+        Some(permutations) => (NullSource::Provided(permutations), None),
+        None => {
+            let seed = config.seed.unwrap_or_else(rand::random);
+            (
+                NullSource::Seeded {
+                    seed,
+                    permutations: config.permutations.max(0) as usize,
+                },
+                Some(seed),
+            )
+        }
+    };
+    let scored: Vec<Result<PartialGSEAResult, GSEAExcludedSet>> = gmt
         .par_iter()
         .map(|analyte_set| {
             // parallelized scoring of all sets
-            analyte_set_p(&analytes, &ranks, analyte_set, 1.0, &permutations, &config)
+            analyte_set_p(analytes, ranks, analyte_set, config.p, &null, config)
         })
         .collect();
+    let indices: Vec<usize> = scored
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.is_ok())
+        .map(|(i, _)| i)
+        .collect();
+    let (partial_results, excluded) = split_excluded(scored);
+    (partial_results, excluded, seed, indices)
+}
+
+/// Separate the scored sets from the excluded sets, keeping the order of both
+fn split_excluded(
+    scored: Vec<Result<PartialGSEAResult, GSEAExcludedSet>>,
+) -> (Vec<PartialGSEAResult>, Vec<GSEAExcludedSet>) {
+    let mut partial_results = Vec::new();
+    let mut excluded = Vec::new();
+    for set in scored {
+        match set {
+            Ok(partial) => partial_results.push(partial),
+            Err(set) => excluded.push(set),
+        }
+    }
+    (partial_results, excluded)
+}
+
+/// Calculates the FDR of every set by comparing its normalized enrichment score to the normalized
+/// null scores of all sets
+fn add_fdrs(
+    partial_results: &[PartialGSEAResult],
+    scheme: WeightingScheme,
+    seed: Option<u64>,
+) -> Vec<GSEAResult> {
     let null_distribution: Vec<f64> = partial_results
         .iter()
-        .flat_map(|x| x.nes_iter.clone())
+        .flat_map(|x| x.nes_iter.iter().copied())
         .collect();
     let observed_distribution: Vec<f64> = partial_results.iter().map(|x| x.nes).collect();
-    let mut final_gsea: Vec<GSEAResult> = Vec::new();
-    let postive_top_side = null_distribution
-        .par_iter()
-        .filter(|&x| x >= &0_f64)
-        .collect();
-    let negative_top_side = null_distribution
-        .par_iter()
-        .filter(|&x| x < &0_f64)
-        .collect();
-    let postive_bottom_side = observed_distribution
+    let fdrs = nes_fdr(&observed_distribution, &null_distribution);
+    partial_results
         .par_iter()
-        .filter(|&x| x >= &0_f64)
-        .collect();
-    let negative_bottom_side = observed_distribution
+        .zip(fdrs)
+        .map(|(item, fdr)| item.add_fdr(fdr, scheme, seed))
+        .collect()
+}
+
+/// Calculates the GSEA FDR of every normalized enrichment score. The FDR of a score is the
+/// fraction of null scores of the same sign at least as extreme, divided by the fraction of
+/// observed scores of the same sign at least as extreme, capped at `1`.
+///
+/// Each side of the null and observed distributions is sorted by absolute value once, so the
+/// count of scores at least as extreme as a set is a binary search instead of a scan. This is
+/// `O((S + N) log N)` instead of `O(S * N)` for `S` sets and `N` null scores.
+///
+/// # Parameters
+///
+/// - `observed` - Normalized enrichment score of every set
+/// - `null_distribution` - Normalized null scores of all sets pooled together
+///
+/// # Returns
+///
+/// Returns the FDR of every score in `observed`, in the same order
+pub fn nes_fdr(observed: &[f64], null_distribution: &[f64]) -> Vec<f64> {
+    let (postive_top_side, negative_top_side) = sorted_sides(null_distribution);
+    let (postive_bottom_side, negative_bottom_side) = sorted_sides(observed);
+    observed
         .par_iter()
-        .filter(|&x| x < &0_f64)
+        .map(|nes| {
+            // get all FDR values
+            let nes = *nes;
+            let top_side = if nes > 0_f64 {
+                // positive null distribution
+                &postive_top_side
+            } else {
+                // negative null distribution
+                &negative_top_side
+            };
+            let top_len = if top_side.is_empty() {
+                // avoid dividing by 0
+                0.000001
+            } else {
+                top_side.len() as f64
+            };
+            let nes_abs = nes.abs();
+            // get count of scores higher than current NES
+            let top_val = count_at_least(top_side, nes_abs) as f64;
+            let bottom_side = if nes >= 0_f64 {
+                &postive_bottom_side
+            } else {
+                &negative_bottom_side
+            };
+            let bottom_len = if bottom_side.is_empty() {
+                0.000001
+            } else {
+                bottom_side.len() as f64
+            };
+            let bottom_val = count_at_least(bottom_side, nes_abs) as f64;
+            let fdr: f64 = (top_val * bottom_len) / (bottom_val * top_len); // get FDR value
+            if fdr.is_nan() {
+                0.0
+            } else if fdr > 1.0 {
+                1.0
+            } else {
+                fdr
+            }
+        })
+        .collect()
+}
+
+/// Split scores into the absolute values of the positive (including zero) and negative scores,
+/// each sorted in ascending order. `NaN` scores are in neither side.
+fn sorted_sides(scores: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let mut positive: Vec<f64> = scores.iter().filter(|x| **x >= 0_f64).copied().collect();
+    let mut negative: Vec<f64> = scores
+        .iter()
+        .filter(|x| **x < 0_f64)
+        .map(|x| x.abs())
         .collect();
-    for item in &partial_results {
-        // get all FDR values
-        let nes = item.nes;
-        let top_side: &Vec<&f64> = if nes > 0_f64 {
-            // positive null distribution
-            &postive_top_side
-        } else {
-            // negative null distribution
-            &negative_top_side
-        };
-        let top_len = if top_side.is_empty() {
-            // avoid dividing by 0
-            0.000001
-        } else {
-            top_side.len() as f64
-        };
-        let nes_abs = nes.abs();
-        let top_val = top_side.par_iter().filter(|&x| x.abs() >= nes_abs).count() as f64; // get
-                                                                                          // count of scores higher than current NES
-        let bottom_side: &Vec<&f64> = if nes >= 0_f64 {
-            &postive_bottom_side
-        } else {
-            &negative_bottom_side
-        };
-        let bottom_len = if bottom_side.is_empty() {
-            0.000001
-        } else {
-            bottom_side.len() as f64
-        };
-        let bottom_val = bottom_side
-            .par_iter()
-            .filter(|&x| x.abs() >= nes_abs)
-            .count() as f64;
-        let fdr: f64 = (top_val * bottom_len) / (bottom_val * top_len); // get FDR value
-        let fdr = if fdr.is_nan() {
-            0.0
-        } else if fdr > 1.0 {
-            1.0
-        } else {
-            fdr
-        };
-        final_gsea.push(item.add_fdr(fdr));
-    }
-    final_gsea
+    positive.par_sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    negative.par_sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    (positive, negative)
 }
 
-/// Create index permutations for GSEA
+/// Number of values in the ascending `sorted` slice that are at least `threshold`. A `NaN`
+/// threshold counts nothing, as a scan with `>=` would.
+fn count_at_least(sorted: &[f64], threshold: f64) -> usize {
+    #[allow(clippy::neg_cmp_op_on_partial_ord)]
+    let below = sorted.partition_point(|x| !(*x >= threshold));
+    sorted.len() - below
+}
+
+/// Create index permutations for GSEA. [`gsea`] samples the hit positions of its null directly, so
+/// these are only needed to provide specific permutations to it.
 ///
 /// # Parameters
 ///
@@ -411,13 +940,102 @@ pub fn gsea(
 /// assert_eq!(permutations[0].len(), 100);
 /// ```
 pub fn make_permutations(permutations: i32, max: usize) -> Vec<Vec<usize>> {
+    make_seeded_permutations(permutations, max, rand::random())
+}
+
+/// Create index permutations for GSEA from a seed. The same seed always gives the same
+/// permutations.
+///
+/// # Parameters
+///
+/// - `permutations` - Number of permutations to create
+/// - `max` - Maximum index to permute
+/// - `seed` - Seed of the random number generator
+///
+/// # Returns
+///
+/// Returns a [`Vec<Vec<usize>>`] of the permutations
+///
+/// # Examples
+///
+/// ```
+/// use webgestalt_lib::methods::gsea::make_seeded_permutations;
+/// let permutations = make_seeded_permutations(10, 100, 42);
+/// assert_eq!(permutations, make_seeded_permutations(10, 100, 42));
+/// ```
+pub fn make_seeded_permutations(permutations: i32, max: usize, seed: u64) -> Vec<Vec<usize>> {
     let mut temp_permutations: Vec<Vec<usize>> = Vec::new();
-    let mut smallrng = rand::rngs::SmallRng::from_entropy();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
     (0..permutations).for_each(|_i| {
         // get random permutations that are shared for all analyte sets
         let mut new_order: Vec<usize> = (0..max).collect();
-        new_order.shuffle(&mut smallrng);
+        new_order.shuffle(&mut rng);
         temp_permutations.push(new_order);
     });
     temp_permutations
 }
+
+/// Leading edge analysis of several analyte sets
+#[derive(Debug, Serialize, Clone)]
+pub struct LeadingEdgeAnalysis {
+    /// The analyte sets, in the order of the input
+    pub sets: Vec<String>,
+    /// Every analyte in any leading edge, sorted by the number of leading edges it is in
+    pub analytes: Vec<String>,
+    /// Analyte by set matrix, `true` if the analyte is in the leading edge of the set
+    pub membership: Vec<Vec<bool>>,
+    /// Set by set matrix of the Jaccard index of the leading edges
+    pub overlap: Vec<Vec<f64>>,
+}
+
+/// Compare the leading edges of analyte sets to find the core analytes shared by several sets.
+/// Only the provided results are used, so they should already be filtered to the significant sets.
+///
+/// # Parameters
+///
+/// - `results` - Slice of [`GSEAResult`] of the sets to compare
+///
+/// # Returns
+///
+/// Returns a [`LeadingEdgeAnalysis`] with the membership and pairwise overlap of the leading edges
+pub fn leading_edge_analysis(results: &[GSEAResult]) -> LeadingEdgeAnalysis {
+    let edges: Vec<AHashSet<&String>> = results
+        .iter()
+        .map(|x| x.leading_edge_analytes.iter().collect())
+        .collect();
+    let mut counts: AHashMap<&String, usize> = AHashMap::default();
+    for edge in edges.iter() {
+        for analyte in edge.iter() {
+            *counts.entry(analyte).or_insert(0) += 1;
+        }
+    }
+    let mut analytes: Vec<&String> = counts.keys().copied().collect();
+    analytes.sort_by(|a, b| counts[b].cmp(&counts[a]).then(a.cmp(b)));
+    let membership: Vec<Vec<bool>> = analytes
+        .iter()
+        .map(|analyte| edges.iter().map(|edge| edge.contains(analyte)).collect())
+        .collect();
+    let overlap: Vec<Vec<f64>> = edges
+        .iter()
+        .map(|a| {
+            edges
+                .iter()
+                .map(|b| {
+                    let intersection = a.intersection(b).count();
+                    let union = a.len() + b.len() - intersection;
+                    if union == 0 {
+                        0.0
+                    } else {
+                        intersection as f64 / union as f64
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    LeadingEdgeAnalysis {
+        sets: results.iter().map(|x| x.set.clone()).collect(),
+        analytes: analytes.into_iter().cloned().collect(),
+        membership,
+        overlap,
+    }
+}
@@ -0,0 +1,295 @@
+use crate::readers::utils::Item;
+use ahash::AHashSet;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use super::{
+    add_fdrs, random_set_es, score_sets, GSEAConfig, GSEAExcludedSet, GSEAResult, RankListItem,
+    WeightingScheme,
+};
+
+/// Parameters for GSEA with multilevel p-values
+#[derive(Clone)]
+pub struct MultilevelGSEAConfig {
+    /// The [`GSEAConfig`] of the simple permutations, which give the NES and FDR of every set
+    pub gsea: GSEAConfig,
+    /// Number of random sets kept at every level. Larger samples give smaller errors
+    pub sample_size: usize,
+    /// Smallest p-value to estimate. Sets below it get `eps` as a bound, without an error estimate
+    pub eps: f64,
+    /// Sets with fewer simple permutations at least as extreme as the observed score use the
+    /// multilevel p-value
+    pub min_exceedances: usize,
+}
+
+impl Default for MultilevelGSEAConfig {
+    fn default() -> Self {
+        MultilevelGSEAConfig {
+            gsea: GSEAConfig::default(),
+            sample_size: 101,
+            eps: 1e-50,
+            min_exceedances: 10,
+        }
+    }
+}
+
+/// GSEA result with an error estimate of the p-value
+#[derive(Debug, Serialize, Clone)]
+pub struct MultilevelGSEAResult {
+    /// The GSEA result, where `p` is from the multilevel scheme if the simple permutations were
+    /// not precise enough
+    #[serde(flatten)]
+    pub result: GSEAResult,
+    /// Standard deviation of `log2(p)`. `None` if `p` is only the `eps` bound
+    pub log2err: Option<f64>,
+    /// Number of levels of the multilevel scheme. `0` if the simple permutation p-value was used
+    pub levels: usize,
+}
+
+/// Run GSEA with p-values from the adaptive multilevel Monte Carlo scheme of fgsea
+/// ([DOI:10.1101/060012](https://doi.org/10.1101/060012)). Sets outside the overlap limits are
+/// left out, as in [`super::gsea`].
+///
+/// Sets that need it start with random sets of the same size and repeatedly keep the half that
+/// scores above the median, refilling the sample with MCMC perturbations that stay above it. Every
+/// level halves the probability, so small p-values need only about `-log2(p)` levels instead of
+/// `1 / p` permutations. Each set is seeded from the permutation seed and its index, so results do
+/// not depend on the number of threads.
+///
+/// # Parameters
+///
+/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list. Analytes with NaN or infinite ranks
+///   are left out
+/// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - The [`MultilevelGSEAConfig`] of the analysis
+///
+/// # Returns
+///
+/// Returns a [`Vec<MultilevelGSEAResult>`] of the GSEA results. Use
+/// [`gsea_multilevel_with_excluded`] to also get the sets outside the overlap limits
+pub fn gsea_multilevel(
+    analyte_list: Vec<RankListItem>,
+    gmt: Vec<Item>,
+    config: MultilevelGSEAConfig,
+) -> Vec<MultilevelGSEAResult> {
+    gsea_multilevel_with_excluded(analyte_list, gmt, config).0
+}
+
+/// Run GSEA with multilevel p-values and also return the sets excluded by the overlap limits.
+///
+/// # Parameters
+///
+/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list. Analytes with NaN or infinite ranks
+///   are left out
+/// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - The [`MultilevelGSEAConfig`] of the analysis
+///
+/// # Returns
+///
+/// Returns a tuple of the [`Vec<MultilevelGSEAResult>`] of the scored sets and a
+/// [`Vec<GSEAExcludedSet>`] of the sets that were left out before scoring
+pub fn gsea_multilevel_with_excluded(
+    mut analyte_list: Vec<RankListItem>,
+    gmt: Vec<Item>,
+    config: MultilevelGSEAConfig,
+) -> (Vec<MultilevelGSEAResult>, Vec<GSEAExcludedSet>) {
+    RankListItem::sort_finite(&mut analyte_list);
+    let (analytes, ranks) = RankListItem::to_vecs(analyte_list);
+    let (partial_results, excluded, seed, indices) =
+        score_sets(&analytes, &ranks, &gmt, &config.gsea, None);
+    let seed = seed.unwrap_or_default();
+    let results = add_fdrs(
+        &partial_results,
+        WeightingScheme::from_p(config.gsea.p),
+        Some(seed),
+    );
+    let weights: Vec<f64> = ranks.iter().map(|x| x.abs().powf(config.gsea.p)).collect();
+    let results = results
+        .into_par_iter()
+        .zip(partial_results.par_iter())
+        .zip(indices.par_iter())
+        .enumerate()
+        .map(|(i, ((result, partial), index))| {
+            let counts = partial.null_counts;
+            if result.es == 0.0 || counts.exceedances >= config.min_exceedances {
+                let log2err = if counts.same_sign == 0 {
+                    None
+                } else {
+                    Some(
+                        (trigamma(counts.exceedances as f64 + 1.0)
+                            - trigamma(counts.same_sign as f64 + 1.0))
+                        .max(0.0)
+                        .sqrt()
+                            / std::f64::consts::LN_2,
+                    )
+                };
+                return MultilevelGSEAResult {
+                    result,
+                    log2err,
+                    levels: 0,
+                };
+            }
+            let analyte_set: AHashSet<&String> = gmt[*index].parts.iter().collect();
+            let size = analytes.iter().filter(|x| analyte_set.contains(x)).count();
+            let inverse_size_dif = 1.0 / ((analytes.len() - analyte_set.len()) as f64);
+            let scorer = RandomSetScorer {
+                weights: &weights,
+                inverse_size_dif,
+                sign: result.es.signum(),
+            };
+            let mut rng = rand::rngs::SmallRng::seed_from_u64(seed.wrapping_add(i as u64));
+            let (log_p, levels, bounded) =
+                multilevel_log_p(&scorer, size, result.es.abs(), &config, &mut rng);
+            // condition on the sign, as the simple permutation p-value does
+            let sign_fraction =
+                (counts.same_sign as f64 + 1.0) / (counts.permutations as f64 + 1.0);
+            let p = if bounded {
+                config.eps
+            } else {
+                (log_p - sign_fraction.ln()).exp().min(1.0)
+            };
+            let log2err = if bounded {
+                None
+            } else {
+                let sample_size = config.sample_size as f64;
+                Some(
+                    ((-p.log2() + 1.0).floor()
+                        * (trigamma((sample_size + 1.0) / 2.0) - trigamma(sample_size + 1.0)))
+                    .sqrt()
+                        / std::f64::consts::LN_2,
+                )
+            };
+            MultilevelGSEAResult {
+                result: GSEAResult { p, ..result },
+                log2err,
+                levels,
+            }
+        })
+        .collect();
+    (results, excluded)
+}
+
+/// Scores random sets given as sorted positions in the rank list
+struct RandomSetScorer<'a> {
+    weights: &'a [f64],
+    inverse_size_dif: f64,
+    sign: f64,
+}
+
+impl RandomSetScorer<'_> {
+    /// Enrichment score of the set with the same sign convention as the observed score, so larger
+    /// is more extreme
+    fn score(&self, positions: &[usize]) -> f64 {
+        self.sign * random_set_es(positions, self.weights, self.inverse_size_dif)
+    }
+}
+
+/// Estimate `ln(P(score >= observed))` for random sets of `size` analytes.
+///
+/// Returns the log probability, the number of levels, and whether the estimate stopped at `eps`
+fn multilevel_log_p(
+    scorer: &RandomSetScorer,
+    size: usize,
+    observed: f64,
+    config: &MultilevelGSEAConfig,
+    rng: &mut rand::rngs::SmallRng,
+) -> (f64, usize, bool) {
+    let n = scorer.weights.len();
+    let sample_size = config.sample_size.max(3);
+    let ln_eps = config.eps.ln();
+    let mut sample: Vec<(Vec<usize>, f64)> = (0..sample_size)
+        .map(|_| {
+            let mut positions = rand::seq::index::sample(rng, n, size).into_vec();
+            positions.sort_unstable();
+            let score = scorer.score(&positions);
+            (positions, score)
+        })
+        .collect();
+    let mut log_p = 0.0;
+    let mut levels = 0;
+    loop {
+        let mut scores: Vec<f64> = sample.iter().map(|x| x.1).collect();
+        scores.sort_by(|a, b| a.total_cmp(b));
+        let threshold = scores[sample_size / 2];
+        if threshold >= observed {
+            break;
+        }
+        let kept: Vec<usize> = (0..sample_size)
+            .filter(|i| sample[*i].1 > threshold)
+            .collect();
+        if kept.is_empty() {
+            // every set has the same score, so the sample can not move up
+            return (ln_eps, levels, true);
+        }
+        log_p += (kept.len() as f64 / sample_size as f64).ln();
+        levels += 1;
+        if log_p < ln_eps {
+            return (ln_eps, levels, true);
+        }
+        sample = (0..sample_size)
+            .map(|i| {
+                let (mut positions, mut score) = sample[kept[i % kept.len()]].clone();
+                perturb(scorer, &mut positions, &mut score, threshold, rng);
+                (positions, score)
+            })
+            .collect();
+    }
+    let above = sample.iter().filter(|x| x.1 >= observed).count();
+    log_p += (above as f64 / sample_size as f64).ln();
+    (log_p, levels, false)
+}
+
+/// Move the set with `size` MCMC steps, each swapping one analyte for a random analyte outside the
+/// set. Steps that would drop the score to `threshold` or below are rejected.
+fn perturb(
+    scorer: &RandomSetScorer,
+    positions: &mut Vec<usize>,
+    score: &mut f64,
+    threshold: f64,
+    rng: &mut rand::rngs::SmallRng,
+) {
+    let n = scorer.weights.len();
+    if positions.len() >= n {
+        return;
+    }
+    let mut candidate: Vec<usize> = Vec::with_capacity(positions.len());
+    for _ in 0..positions.len() {
+        let removed = rng.gen_range(0..positions.len());
+        let added = loop {
+            let x = rng.gen_range(0..n);
+            if positions.binary_search(&x).is_err() {
+                break x;
+            }
+        };
+        candidate.clear();
+        candidate.extend(positions.iter().enumerate().filter_map(|(i, x)| {
+            if i == removed {
+                None
+            } else {
+                Some(*x)
+            }
+        }));
+        let insert_at = candidate.partition_point(|x| *x < added);
+        candidate.insert(insert_at, added);
+        let new_score = scorer.score(&candidate);
+        if new_score > threshold {
+            std::mem::swap(positions, &mut candidate);
+            *score = new_score;
+        }
+    }
+}
+
+/// Trigamma function, the second derivative of `ln(gamma(x))`, for `x > 0`
+fn trigamma(mut x: f64) -> f64 {
+    let mut result = 0.0;
+    while x < 6.0 {
+        result += 1.0 / (x * x);
+        x += 1.0;
+    }
+    let x2 = 1.0 / (x * x);
+    result
+        + 1.0 / x
+        + x2 / 2.0
+        + x2 / x * (1.0 / 6.0 - x2 * (1.0 / 30.0 - x2 * (1.0 / 42.0 - x2 / 30.0)))
+}
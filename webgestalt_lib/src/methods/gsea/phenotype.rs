@@ -0,0 +1,410 @@
+use crate::readers::utils::{ExpressionMatrix, Item, Phenotype};
+use ahash::AHashSet;
+use rand::prelude::SliceRandom;
+use rand::SeedableRng;
+use rayon::prelude::*;
+
+use super::{
+    add_fdrs, enrichment_score, leading_edge, null_statistics, split_excluded, GSEAConfig,
+    GSEAExcludedSet, GSEAResult, PartialGSEAResult, RankListItem, WeightingScheme,
+};
+
+/// Metric used to rank the analytes of an expression matrix by the phenotype.
+///
+/// The class metrics compare the first class of the CLS file (`A`) to the second (`B`), so
+/// positive scores are higher in `A`. As in the Broad GSEA implementation, the standard deviation
+/// of each class is at least `0.2 * |mean|`, and at least `0.2` if the mean is `0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RankMetric {
+    /// `(mean_A - mean_B) / (sd_A + sd_B)`
+    SignalToNoise,
+    /// `(mean_A - mean_B) / sqrt(sd_A^2 / n_A + sd_B^2 / n_B)`
+    TTest,
+    /// `mean_A / mean_B`
+    Ratio,
+    /// `log2(mean_A / mean_B)`. Only meaningful for positive values
+    Log2Ratio,
+    /// `mean_A - mean_B`
+    Difference,
+    /// Pearson correlation with the phenotype. Works with continuous phenotypes, or with two
+    /// classes coded as `1` for `A` and `0` for `B`
+    Pearson,
+}
+
+impl RankMetric {
+    /// Whether the metric needs exactly two classes
+    fn is_class_metric(&self) -> bool {
+        !matches!(self, RankMetric::Pearson)
+    }
+}
+
+/// Parameters for phenotype permutation GSEA
+#[derive(Clone)]
+pub struct PhenotypeGSEAConfig {
+    /// The [`GSEAConfig`] used for the weighting, overlap limits, permutations, and seed
+    pub gsea: GSEAConfig,
+    /// The [`RankMetric`] used to rank the analytes
+    pub metric: RankMetric,
+}
+
+impl Default for PhenotypeGSEAConfig {
+    fn default() -> Self {
+        PhenotypeGSEAConfig {
+            gsea: GSEAConfig::default(),
+            metric: RankMetric::SignalToNoise,
+        }
+    }
+}
+
+/// Rank the analytes of an expression matrix by their association with the phenotype.
+/// Scores that are not finite (such as a ratio with a zero mean) are set to `0`.
+///
+/// # Parameters
+///
+/// - `matrix` - The [`ExpressionMatrix`] of the samples
+/// - `phenotype` - The [`Phenotype`] of the samples
+/// - `metric` - The [`RankMetric`] to use
+///
+/// # Panics
+///
+/// Panics if the phenotype does not have one value per sample, or if a class metric is used
+/// without exactly two classes.
+///
+/// # Returns
+///
+/// Returns a [`Vec<RankListItem>`] with the score of every analyte, in the order of the matrix
+pub fn rank_metric(
+    matrix: &ExpressionMatrix,
+    phenotype: &Phenotype,
+    metric: RankMetric,
+) -> Vec<RankListItem> {
+    check_phenotype(matrix, phenotype, metric);
+    matrix
+        .analytes
+        .iter()
+        .zip(score_rows(matrix, phenotype, metric))
+        .map(|(analyte, rank)| RankListItem {
+            analyte: analyte.clone(),
+            rank,
+        })
+        .collect()
+}
+
+/// Run GSEA on an expression matrix, permuting the sample labels instead of the analytes. This
+/// keeps the correlation between analytes in the null distribution, and is preferred when there
+/// are enough samples (at least seven per class is a common guideline).
+///
+/// # Parameters
+///
+/// - `matrix` - The [`ExpressionMatrix`] of the samples
+/// - `phenotype` - The [`Phenotype`] of the samples
+/// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - The [`PhenotypeGSEAConfig`] of the analysis
+///
+/// # Panics
+///
+/// Panics if the phenotype does not have one value per sample, or if a class metric is used
+/// without exactly two classes.
+///
+/// # Returns
+///
+/// Returns a [`Vec<GSEAResult>`] of the GSEA results. Sets outside the overlap limits are left
+/// out, see [`gsea_phenotype_with_excluded`]
+pub fn gsea_phenotype(
+    matrix: &ExpressionMatrix,
+    phenotype: &Phenotype,
+    gmt: Vec<Item>,
+    config: PhenotypeGSEAConfig,
+) -> Vec<GSEAResult> {
+    gsea_phenotype_with_excluded(matrix, phenotype, gmt, config).0
+}
+
+/// Run phenotype permutation GSEA and also return the sets excluded by the overlap limits.
+///
+/// # Parameters
+///
+/// - `matrix` - The [`ExpressionMatrix`] of the samples
+/// - `phenotype` - The [`Phenotype`] of the samples
+/// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - The [`PhenotypeGSEAConfig`] of the analysis
+///
+/// # Panics
+///
+/// Panics under the same conditions as [`gsea_phenotype`].
+///
+/// # Returns
+///
+/// Returns a tuple of the [`Vec<GSEAResult>`] of the scored sets and a [`Vec<GSEAExcludedSet>`]
+/// of the sets that were left out before scoring
+pub fn gsea_phenotype_with_excluded(
+    matrix: &ExpressionMatrix,
+    phenotype: &Phenotype,
+    gmt: Vec<Item>,
+    config: PhenotypeGSEAConfig,
+) -> (Vec<GSEAResult>, Vec<GSEAExcludedSet>) {
+    check_phenotype(matrix, phenotype, config.metric);
+    let p = config.gsea.p;
+    let seed = config.gsea.seed.unwrap_or_else(rand::random);
+    let analyte_count = matrix.analytes.len();
+    let observed = score_rows(matrix, phenotype, config.metric);
+    let order = sorted_order(&observed);
+    let analytes: Vec<String> = order.iter().map(|i| matrix.analytes[*i].clone()).collect();
+    let weights = |scores: &[f64], order: &[usize]| -> Vec<f64> {
+        order.iter().map(|i| scores[*i].abs().powf(p)).collect()
+    };
+    let observed_weights = weights(&observed, &order);
+    // membership of every set by matrix row, and the GSEA constants of the set
+    let sets: Vec<Result<SetMembership, GSEAExcludedSet>> = gmt
+        .par_iter()
+        .map(|item| {
+            let analyte_set: AHashSet<&String> = item.parts.iter().collect();
+            let has_analyte: Vec<bool> = matrix
+                .analytes
+                .iter()
+                .map(|x| analyte_set.contains(x))
+                .collect();
+            let overlap = has_analyte.iter().filter(|x| **x).count() as i32;
+            match config
+                .gsea
+                .exclusion_reason(overlap, analyte_set.len(), analyte_count)
+            {
+                Some(reason) => Err(GSEAExcludedSet {
+                    set: item.id.clone(),
+                    overlap,
+                    reason,
+                }),
+                None => {
+                    let inverse_size_dif = 1.0 / ((analyte_count - analyte_set.len()) as f64);
+                    Ok((has_analyte, overlap, inverse_size_dif))
+                }
+            }
+        })
+        .collect();
+    let label_permutations =
+        make_phenotype_permutations(phenotype, config.gsea.permutations.max(0) as usize, seed);
+    // null enrichment scores, one row per permutation
+    let null_es: Vec<Vec<f64>> = label_permutations
+        .par_iter()
+        .map(|permuted| {
+            let scores = score_rows(matrix, permuted, config.metric);
+            let perm_order = sorted_order(&scores);
+            let perm_weights = weights(&scores, &perm_order);
+            sets.iter()
+                .map(|set| match set {
+                    Ok((has_analyte, _, inverse_size_dif)) => {
+                        enrichment_score(
+                            has_analyte,
+                            &perm_weights,
+                            &perm_order,
+                            *inverse_size_dif,
+                            0.0,
+                            true,
+                        )
+                        .0
+                    }
+                    Err(_) => 0.0,
+                })
+                .collect()
+        })
+        .collect();
+    let scored: Vec<Result<PartialGSEAResult, GSEAExcludedSet>> = gmt
+        .par_iter()
+        .zip(sets.par_iter())
+        .enumerate()
+        .map(|(s, (item, set))| match set {
+            Err(excluded) => Err(excluded.clone()),
+            Ok((has_analyte, overlap, inverse_size_dif)) => {
+                let n_r: f64 = order
+                    .iter()
+                    .zip(observed_weights.iter())
+                    .filter(|(i, _)| has_analyte[**i])
+                    .map(|(_, w)| w)
+                    .sum();
+                let (real_es, max_index, running_sum) = enrichment_score(
+                    has_analyte,
+                    &observed_weights,
+                    &order,
+                    *inverse_size_dif,
+                    1.0 / n_r,
+                    false,
+                );
+                let es_iter: Vec<f64> = null_es.iter().map(|x| x[s]).collect();
+                let (p, nes, nes_iter, null_counts) = null_statistics(real_es, &es_iter);
+                let (running_sum, running_sum_positions) =
+                    config.gsea.running_sum.store(running_sum);
+                let sorted_has_analyte: Vec<bool> = order.iter().map(|i| has_analyte[*i]).collect();
+                Ok(PartialGSEAResult {
+                    set: item.id.clone(),
+                    p,
+                    nes,
+                    es: real_es,
+                    leading_edge: leading_edge(
+                        &analytes,
+                        &sorted_has_analyte,
+                        real_es,
+                        max_index,
+                        *overlap,
+                    ),
+                    running_sum,
+                    running_sum_positions,
+                    nes_iter,
+                    null_counts,
+                })
+            }
+        })
+        .collect();
+    let (partial_results, excluded) = split_excluded(scored);
+    (
+        add_fdrs(&partial_results, WeightingScheme::from_p(p), Some(seed)),
+        excluded,
+    )
+}
+
+/// Membership of a set by matrix row, its overlap, and the inverse of the number of analytes not
+/// in the set
+type SetMembership = (Vec<bool>, i32, f64);
+
+/// Create phenotypes with shuffled sample labels. The same seed always gives the same
+/// permutations.
+fn make_phenotype_permutations(
+    phenotype: &Phenotype,
+    permutations: usize,
+    seed: u64,
+) -> Vec<Phenotype> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    (0..permutations)
+        .map(|_| match phenotype {
+            Phenotype::Categorical { classes, labels } => {
+                let mut labels = labels.clone();
+                labels.shuffle(&mut rng);
+                Phenotype::Categorical {
+                    classes: classes.clone(),
+                    labels,
+                }
+            }
+            Phenotype::Continuous { name, values } => {
+                let mut values = values.clone();
+                values.shuffle(&mut rng);
+                Phenotype::Continuous {
+                    name: name.clone(),
+                    values,
+                }
+            }
+        })
+        .collect()
+}
+
+/// Row indices sorted by decreasing score. Ties keep the order of the matrix.
+fn sorted_order(scores: &[f64]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|a, b| scores[*b].partial_cmp(&scores[*a]).unwrap());
+    order
+}
+
+fn check_phenotype(matrix: &ExpressionMatrix, phenotype: &Phenotype, metric: RankMetric) {
+    match phenotype {
+        Phenotype::Categorical { classes, labels } => {
+            if labels.len() != matrix.samples.len() {
+                panic!(
+                    "Phenotype has {} labels, but the matrix has {} samples",
+                    labels.len(),
+                    matrix.samples.len()
+                );
+            }
+            if classes.len() != 2 {
+                panic!(
+                    "Phenotype must have two classes to rank analytes, found {}",
+                    classes.len()
+                );
+            }
+        }
+        Phenotype::Continuous { values, .. } => {
+            if values.len() != matrix.samples.len() {
+                panic!(
+                    "Phenotype has {} values, but the matrix has {} samples",
+                    values.len(),
+                    matrix.samples.len()
+                );
+            }
+            if metric.is_class_metric() {
+                panic!("Continuous phenotypes can only be ranked with the Pearson metric");
+            }
+        }
+    }
+}
+
+/// Score every row of the matrix. Non-finite scores are set to `0`.
+fn score_rows(matrix: &ExpressionMatrix, phenotype: &Phenotype, metric: RankMetric) -> Vec<f64> {
+    matrix
+        .values
+        .par_iter()
+        .map(|row| {
+            let score = match phenotype {
+                Phenotype::Categorical { labels, .. } => {
+                    if metric == RankMetric::Pearson {
+                        let coded: Vec<f64> = labels
+                            .iter()
+                            .map(|x| if *x == 0 { 1.0 } else { 0.0 })
+                            .collect();
+                        pearson(row, &coded)
+                    } else {
+                        class_score(row, labels, metric)
+                    }
+                }
+                Phenotype::Continuous { values, .. } => pearson(row, values),
+            };
+            if score.is_finite() {
+                score
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// Mean, adjusted standard deviation, and size of one class
+fn class_stats(row: &[f64], labels: &[usize], class: usize) -> (f64, f64, f64) {
+    let values: Vec<f64> = row
+        .iter()
+        .zip(labels)
+        .filter(|(_, label)| **label == class)
+        .map(|(x, _)| *x)
+        .collect();
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let sd = if values.len() > 1 {
+        (values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+    } else {
+        0.0
+    };
+    let min_sd = if mean == 0.0 { 0.2 } else { 0.2 * mean.abs() };
+    (mean, sd.max(min_sd), n)
+}
+
+fn class_score(row: &[f64], labels: &[usize], metric: RankMetric) -> f64 {
+    let (mean_a, sd_a, n_a) = class_stats(row, labels, 0);
+    let (mean_b, sd_b, n_b) = class_stats(row, labels, 1);
+    match metric {
+        RankMetric::SignalToNoise => (mean_a - mean_b) / (sd_a + sd_b),
+        RankMetric::TTest => (mean_a - mean_b) / (sd_a.powi(2) / n_a + sd_b.powi(2) / n_b).sqrt(),
+        RankMetric::Ratio => mean_a / mean_b,
+        RankMetric::Log2Ratio => (mean_a / mean_b).log2(),
+        RankMetric::Difference => mean_a - mean_b,
+        RankMetric::Pearson => unreachable!(),
+    }
+}
+
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+    let mut cov = 0.0;
+    let mut var_x = 0.0;
+    let mut var_y = 0.0;
+    for (a, b) in x.iter().zip(y) {
+        cov += (a - mean_x) * (b - mean_y);
+        var_x += (a - mean_x).powi(2);
+        var_y += (b - mean_y).powi(2);
+    }
+    cov / (var_x * var_y).sqrt()
+}
@@ -0,0 +1,198 @@
+use crate::{StatisticsError, WebGestaltError};
+use ahash::AHashMap;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+
+use super::RankListItem;
+
+/// How to handle analytes that appear more than once in a rank list
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Keep the entry with the largest absolute rank
+    MaxAbs,
+    /// Use the mean rank of the entries
+    Mean,
+    /// Keep the first entry
+    First,
+    /// Return an error
+    Error,
+}
+
+/// How to order analytes with the same rank
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TiePolicy {
+    /// Random order from a seed
+    Random { seed: u64 },
+    /// Alphabetical order of the analyte names
+    ByName,
+}
+
+/// How to handle NaN and infinite ranks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NonFinitePolicy {
+    /// Remove the analyte
+    Drop,
+    /// Return an error
+    Error,
+}
+
+/// Parameters for rank list preparation
+#[derive(Debug, Clone)]
+pub struct RankListConfig {
+    pub duplicates: DuplicatePolicy,
+    pub ties: TiePolicy,
+    pub non_finite: NonFinitePolicy,
+}
+
+impl Default for RankListConfig {
+    fn default() -> Self {
+        RankListConfig {
+            duplicates: DuplicatePolicy::MaxAbs,
+            ties: TiePolicy::ByName,
+            non_finite: NonFinitePolicy::Drop,
+        }
+    }
+}
+
+/// An analyte that appeared more than once
+#[derive(Debug, Serialize, Clone)]
+pub struct DuplicateAnalyte {
+    /// The analyte name
+    pub analyte: String,
+    /// Number of entries of the analyte
+    pub entries: usize,
+    /// The rank that was kept
+    pub rank: f64,
+}
+
+/// Changes made to a rank list during preparation
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct RankListReport {
+    /// Number of entries in the input list
+    pub input_size: usize,
+    /// Number of analytes in the prepared list
+    pub output_size: usize,
+    /// Analytes removed for a NaN or infinite rank
+    pub dropped: Vec<String>,
+    /// Analytes that were merged into one entry
+    pub duplicates: Vec<DuplicateAnalyte>,
+    /// Number of groups of analytes that share a rank
+    pub tie_groups: usize,
+    /// Number of analytes in those groups
+    pub tied_analytes: usize,
+}
+
+/// Prepare a rank list for GSEA by removing non-finite ranks, merging duplicate analytes, and
+/// sorting by decreasing rank with a defined order for ties. [`super::gsea`] keeps the order of
+/// ties in a sorted list, so the tie policy carries over to the analysis.
+///
+/// # Parameters
+///
+/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list
+/// - `config` - The [`RankListConfig`] of the policies
+///
+/// # Returns
+///
+/// Returns the sorted [`Vec<RankListItem>`] and a [`RankListReport`] of the changes, or a
+/// [`WebGestaltError::StatisticsError`] if an error policy was triggered
+pub fn prepare_rank_list(
+    analyte_list: Vec<RankListItem>,
+    config: &RankListConfig,
+) -> Result<(Vec<RankListItem>, RankListReport), WebGestaltError> {
+    let mut report = RankListReport {
+        input_size: analyte_list.len(),
+        ..Default::default()
+    };
+    // entries of every analyte, in order of first appearance
+    let mut order: Vec<String> = Vec::new();
+    let mut entries: AHashMap<String, Vec<f64>> = AHashMap::default();
+    for item in analyte_list {
+        if !item.rank.is_finite() {
+            match config.non_finite {
+                NonFinitePolicy::Drop => {
+                    report.dropped.push(item.analyte);
+                    continue;
+                }
+                NonFinitePolicy::Error => {
+                    return Err(WebGestaltError::StatisticsError(
+                        StatisticsError::NonFiniteValue {
+                            analyte: item.analyte,
+                            value: item.rank,
+                        },
+                    ))
+                }
+            }
+        }
+        match entries.get_mut(&item.analyte) {
+            Some(ranks) => {
+                if config.duplicates == DuplicatePolicy::Error {
+                    return Err(WebGestaltError::StatisticsError(
+                        StatisticsError::DuplicateAnalyte {
+                            analyte: item.analyte,
+                        },
+                    ));
+                }
+                ranks.push(item.rank)
+            }
+            None => {
+                order.push(item.analyte.clone());
+                entries.insert(item.analyte, vec![item.rank]);
+            }
+        }
+    }
+    let mut prepared: Vec<RankListItem> = order
+        .into_iter()
+        .map(|analyte| {
+            let ranks = &entries[&analyte];
+            let rank = match config.duplicates {
+                DuplicatePolicy::MaxAbs => {
+                    ranks
+                        .iter()
+                        .fold(ranks[0], |a, b| if b.abs() > a.abs() { *b } else { a })
+                }
+                DuplicatePolicy::Mean => ranks.iter().sum::<f64>() / ranks.len() as f64,
+                DuplicatePolicy::First | DuplicatePolicy::Error => ranks[0],
+            };
+            if ranks.len() > 1 {
+                report.duplicates.push(DuplicateAnalyte {
+                    analyte: analyte.clone(),
+                    entries: ranks.len(),
+                    rank,
+                });
+            }
+            RankListItem { analyte, rank }
+        })
+        .collect();
+    match config.ties {
+        TiePolicy::ByName => prepared.sort_by(|a, b| {
+            b.rank
+                .partial_cmp(&a.rank)
+                .unwrap()
+                .then_with(|| a.analyte.cmp(&b.analyte))
+        }),
+        TiePolicy::Random { seed } => {
+            // shuffle keys are drawn in name order, so they do not depend on the input order
+            prepared.sort_by(|a, b| a.analyte.cmp(&b.analyte));
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            let mut keyed: Vec<(u64, RankListItem)> =
+                prepared.into_iter().map(|x| (rng.gen(), x)).collect();
+            keyed.sort_by(|a, b| b.1.rank.partial_cmp(&a.1.rank).unwrap().then(a.0.cmp(&b.0)));
+            prepared = keyed.into_iter().map(|(_, x)| x).collect();
+        }
+    }
+    let mut start = 0;
+    while start < prepared.len() {
+        let end = start
+            + prepared[start..]
+                .iter()
+                .take_while(|x| x.rank == prepared[start].rank)
+                .count();
+        if end - start > 1 {
+            report.tie_groups += 1;
+            report.tied_analytes += end - start;
+        }
+        start = end;
+    }
+    report.output_size = prepared.len();
+    Ok((prepared, report))
+}
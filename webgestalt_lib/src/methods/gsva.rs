@@ -0,0 +1,278 @@
+use crate::readers::utils::{ExpressionMatrix, Item};
+use ahash::AHashMap;
+use rayon::prelude::*;
+use serde::Serialize;
+use statrs::distribution::{ContinuousCDF, DiscreteCDF, Normal, Poisson};
+
+/// Kernel used by GSVA to estimate the cumulative distribution of every analyte across samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GSVAKernel {
+    /// Gaussian kernel for continuous values, such as log-transformed microarray or RNA-seq data
+    Gaussian,
+    /// Poisson kernel for integer counts
+    Poisson,
+}
+
+/// Method to score each set in each sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SingleSampleMethod {
+    /// Single-sample GSEA ([DOI:10.1038/nature08460](https://doi.org/10.1038/nature08460))
+    SsGSEA {
+        /// Power to raise the ranks of the set analytes. The usual value is `0.25`
+        alpha: f64,
+        /// Divide all scores by the range of the scores
+        normalize: bool,
+    },
+    /// Gene set variation analysis ([DOI:10.1186/1471-2105-14-7](https://doi.org/10.1186/1471-2105-14-7))
+    GSVA {
+        /// The [`GSVAKernel`] of the cumulative distribution estimate
+        kernel: GSVAKernel,
+        /// Power to raise the rank scores of the set analytes. The usual value is `1`
+        tau: f64,
+        /// Use the difference of the largest positive and negative deviations instead of the
+        /// largest deviation
+        max_diff: bool,
+    },
+}
+
+/// Parameters for single-sample scoring
+#[derive(Clone)]
+pub struct SingleSampleConfig {
+    /// The [`SingleSampleMethod`] of the scores
+    pub method: SingleSampleMethod,
+    /// Minimum number of set analytes in the matrix for the set to be scored
+    pub min_size: usize,
+    /// Maximum number of set analytes in the matrix for the set to be scored
+    pub max_size: usize,
+}
+
+impl Default for SingleSampleConfig {
+    fn default() -> Self {
+        SingleSampleConfig {
+            method: SingleSampleMethod::GSVA {
+                kernel: GSVAKernel::Gaussian,
+                tau: 1.0,
+                max_diff: true,
+            },
+            min_size: 1,
+            max_size: usize::MAX,
+        }
+    }
+}
+
+/// Set by sample matrix of enrichment scores
+#[derive(Debug, Serialize, Clone)]
+pub struct SampleScores {
+    /// The scored sets, one per row of `scores`
+    pub sets: Vec<String>,
+    /// The samples, one per column of `scores`
+    pub samples: Vec<String>,
+    /// Score of every set in every sample
+    pub scores: Vec<Vec<f64>>,
+}
+
+/// Ranking of the analytes in one sample
+struct SampleRanking {
+    /// Position of every matrix row in the sorted list, starting at 0 for the top
+    positions: Vec<usize>,
+    /// Weight of every position of the sorted list
+    weights: Vec<f64>,
+}
+
+/// Score every analyte set in every sample of an expression matrix. Ties are broken by the order
+/// of the matrix rows. Analytes with a NaN or infinite value in any sample are left out.
+///
+/// # Parameters
+///
+/// - `matrix` - The [`ExpressionMatrix`] of the samples
+/// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - The [`SingleSampleConfig`] of the analysis
+///
+/// # Returns
+///
+/// Returns the [`SampleScores`] of the sets within the size limits
+pub fn single_sample_scores(
+    matrix: &ExpressionMatrix,
+    gmt: Vec<Item>,
+    config: SingleSampleConfig,
+) -> SampleScores {
+    let kept: Vec<usize> = (0..matrix.analytes.len())
+        .filter(|i| matrix.values[*i].iter().all(|x| x.is_finite()))
+        .collect();
+    let values: Vec<&[f64]> = kept.iter().map(|i| matrix.values[*i].as_slice()).collect();
+    let analyte_count = kept.len();
+    let sample_count = matrix.samples.len();
+    let columns: Vec<Vec<f64>> = match config.method {
+        SingleSampleMethod::SsGSEA { .. } => (0..sample_count)
+            .map(|j| values.iter().map(|row| row[j]).collect())
+            .collect(),
+        SingleSampleMethod::GSVA { kernel, .. } => kernel_cdf(&values, kernel),
+    };
+    let rankings: Vec<SampleRanking> = columns
+        .par_iter()
+        .map(|column| {
+            let mut order: Vec<usize> = (0..analyte_count).collect();
+            order.sort_by(|a, b| column[*b].total_cmp(&column[*a]));
+            let mut positions = vec![0; analyte_count];
+            for (position, row) in order.iter().enumerate() {
+                positions[*row] = position;
+            }
+            let weights = (0..analyte_count)
+                .map(|position| match config.method {
+                    SingleSampleMethod::SsGSEA { alpha, .. } => {
+                        ((analyte_count - position) as f64).powf(alpha)
+                    }
+                    SingleSampleMethod::GSVA { tau, .. } => (analyte_count as f64 / 2.0
+                        - (position + 1) as f64)
+                        .abs()
+                        .powf(tau),
+                })
+                .collect();
+            SampleRanking { positions, weights }
+        })
+        .collect();
+    let rows: AHashMap<&String, usize> = kept
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (&matrix.analytes[*x], i))
+        .collect();
+    let scored: Vec<(String, Vec<f64>)> = gmt
+        .par_iter()
+        .filter_map(|item| {
+            let mut set_rows: Vec<usize> = item
+                .parts
+                .iter()
+                .filter_map(|x| rows.get(x).copied())
+                .collect();
+            set_rows.sort_unstable();
+            set_rows.dedup();
+            if set_rows.len() < config.min_size
+                || set_rows.len() > config.max_size
+                || set_rows.len() == analyte_count
+            {
+                return None;
+            }
+            let scores = rankings
+                .iter()
+                .map(|ranking| {
+                    let mut hits: Vec<usize> =
+                        set_rows.iter().map(|x| ranking.positions[*x]).collect();
+                    hits.sort_unstable();
+                    match config.method {
+                        SingleSampleMethod::SsGSEA { .. } => walk_sum(&hits, &ranking.weights),
+                        SingleSampleMethod::GSVA { max_diff, .. } => {
+                            let (max, min) = walk_extremes(&hits, &ranking.weights);
+                            if max_diff {
+                                max + min
+                            } else if max > -min {
+                                max
+                            } else {
+                                min
+                            }
+                        }
+                    }
+                })
+                .collect();
+            Some((item.id.clone(), scores))
+        })
+        .collect();
+    let (sets, mut scores): (Vec<String>, Vec<Vec<f64>>) = scored.into_iter().unzip();
+    if let SingleSampleMethod::SsGSEA {
+        normalize: true, ..
+    } = config.method
+    {
+        let all = scores.iter().flatten();
+        let max = all.clone().fold(f64::NEG_INFINITY, |a, b| a.max(*b));
+        let min = all.fold(f64::INFINITY, |a, b| a.min(*b));
+        let range = max - min;
+        if range > 0.0 {
+            scores.iter_mut().flatten().for_each(|x| *x /= range);
+        }
+    }
+    SampleScores {
+        sets,
+        samples: matrix.samples.clone(),
+        scores,
+    }
+}
+
+/// Kernel estimate of the cumulative distribution of every analyte at every sample, using the
+/// values of the analyte in all samples. Returns one column per sample.
+fn kernel_cdf(values: &[&[f64]], kernel: GSVAKernel) -> Vec<Vec<f64>> {
+    let rows: Vec<Vec<f64>> = values
+        .par_iter()
+        .map(|row| {
+            let n = row.len() as f64;
+            match kernel {
+                GSVAKernel::Gaussian => {
+                    let mean = row.iter().sum::<f64>() / n;
+                    let sd = (row.iter().map(|x| (x - mean).powi(2)).sum::<f64>()
+                        / (n - 1.0).max(1.0))
+                    .sqrt();
+                    let bandwidth = sd / 4.0;
+                    if bandwidth == 0.0 || !bandwidth.is_finite() {
+                        return vec![0.5; row.len()];
+                    }
+                    let normal = Normal::new(0.0, 1.0).unwrap();
+                    row.iter()
+                        .map(|x| {
+                            row.iter()
+                                .map(|y| normal.cdf((x - y) / bandwidth))
+                                .sum::<f64>()
+                                / n
+                        })
+                        .collect()
+                }
+                GSVAKernel::Poisson => row
+                    .iter()
+                    .map(|x| {
+                        let count = x.max(0.0).floor() as u64;
+                        row.iter()
+                            .map(|y| Poisson::new(y.max(0.0) + 0.5).unwrap().cdf(count))
+                            .sum::<f64>()
+                            / n
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    let samples = values.first().map_or(0, |x| x.len());
+    (0..samples)
+        .map(|j| rows.iter().map(|row| row[j]).collect())
+        .collect()
+}
+
+/// Sum of the ssGSEA random walk over the whole list, from the sorted hit positions. Each hit
+/// adds its weight to every later step and each miss subtracts from every later step, so the sum
+/// only needs the hits.
+fn walk_sum(hits: &[usize], weights: &[f64]) -> f64 {
+    let n = weights.len() as f64;
+    let misses = n - hits.len() as f64;
+    let total_weight: f64 = hits.iter().map(|x| weights[*x]).sum();
+    let hit_steps: f64 = hits
+        .iter()
+        .map(|x| weights[*x] * (n - *x as f64))
+        .sum::<f64>()
+        / total_weight;
+    let all_steps = n * (n + 1.0) / 2.0;
+    let hit_step_count: f64 = hits.iter().map(|x| n - *x as f64).sum();
+    hit_steps - (all_steps - hit_step_count) / misses
+}
+
+/// Largest positive and negative deviation of the GSVA random walk, from the sorted hit
+/// positions. Between hits the walk only decreases, so the extremes are right before or after a
+/// hit.
+fn walk_extremes(hits: &[usize], weights: &[f64]) -> (f64, f64) {
+    let misses = (weights.len() - hits.len()) as f64;
+    let total_weight: f64 = hits.iter().map(|x| weights[*x]).sum();
+    let mut max: f64 = 0.0;
+    let mut min: f64 = 0.0;
+    let mut sum_hits = 0.0;
+    for (i, position) in hits.iter().enumerate() {
+        let miss_part = (position - i) as f64 / misses;
+        min = min.min(sum_hits / total_weight - miss_part);
+        sum_hits += weights[*position];
+        max = max.max(sum_hits / total_weight - miss_part);
+    }
+    (max, min)
+}
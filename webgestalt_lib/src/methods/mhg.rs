@@ -0,0 +1,206 @@
+use crate::{readers::utils::Item, stat};
+use ahash::AHashSet;
+use rayon::prelude::*;
+use serde::Serialize;
+use statrs::function::factorial::ln_binomial;
+
+use super::gsea::RankListItem;
+
+/// Parameters for the minimum-hypergeometric test
+#[derive(Clone)]
+pub struct MHGConfig {
+    /// Minimum number of set analytes above a cutoff for it to be considered (`X` of XL-mHG)
+    pub min_hits: usize,
+    /// Largest cutoff to consider (`L` of XL-mHG). `None` considers the whole list
+    pub max_cutoff: Option<usize>,
+    /// Minimum number of set analytes in the rank list for the set to be tested
+    pub min_set_size: usize,
+    /// Maximum number of set analytes in the rank list for the set to be tested
+    pub max_set_size: usize,
+    pub fdr_method: stat::AdjustmentMethod,
+}
+
+impl Default for MHGConfig {
+    fn default() -> Self {
+        MHGConfig {
+            min_hits: 1,
+            max_cutoff: None,
+            min_set_size: 5,
+            max_set_size: 500,
+            fdr_method: stat::AdjustmentMethod::BH,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MHGResult {
+    /// The set name
+    pub set: String,
+    /// The exact p-value of the mHG statistic
+    pub p: f64,
+    /// The FDR value
+    pub fdr: f64,
+    /// The mHG statistic, the smallest hypergeometric tail probability over all cutoffs
+    pub mhg: f64,
+    /// The number of top ranked analytes at the optimal cutoff
+    pub cutoff: usize,
+    /// Number of set analytes above the optimal cutoff
+    pub overlap: usize,
+    /// Number of set analytes in the rank list
+    pub set_size: usize,
+}
+
+/// Run the threshold-free minimum-hypergeometric (XL-mHG) test
+/// ([DOI:10.1371/journal.pcbi.0030039](https://doi.org/10.1371/journal.pcbi.0030039),
+/// [DOI:10.7287/peerj.preprints.1962v3](https://doi.org/10.7287/peerj.preprints.1962v3)).
+///
+/// For every set, the hypergeometric tail probability is calculated at every cutoff of the
+/// ranked list and the smallest one is kept. The exact p-value of that statistic is found with
+/// dynamic programming over all orderings of the list.
+///
+/// # Parameters
+///
+/// - `analyte_list` - [`Vec<RankListItem>`] of the rank list. Higher ranks are at the top, and
+///   analytes with NaN or infinite ranks are left out
+/// - `gmt` - [`Vec<Item>`] of gmt file
+/// - `config` - [`MHGConfig`] of the analysis
+///
+/// # Returns
+///
+/// Returns a [`Vec<MHGResult>`] of the sets within the size limits
+pub fn get_mhg(
+    mut analyte_list: Vec<RankListItem>,
+    gmt: Vec<Item>,
+    config: MHGConfig,
+) -> Vec<MHGResult> {
+    RankListItem::sort_finite(&mut analyte_list);
+    let (analytes, _) = RankListItem::to_vecs(analyte_list);
+    let partials: Vec<MHGResult> = gmt
+        .par_iter()
+        .filter_map(|item| {
+            let set: AHashSet<&String> = item.parts.iter().collect();
+            let hits: Vec<bool> = analytes.iter().map(|x| set.contains(x)).collect();
+            let set_size = hits.iter().filter(|x| **x).count();
+            if set_size < config.min_set_size || set_size > config.max_set_size {
+                return None;
+            }
+            let (mhg, cutoff, overlap) = mhg_statistic(&hits, set_size, &config);
+            let p = mhg_p(analytes.len(), set_size, mhg, &config);
+            Some(MHGResult {
+                set: item.id.clone(),
+                p,
+                fdr: 1.0,
+                mhg,
+                cutoff,
+                overlap,
+                set_size,
+            })
+        })
+        .collect();
+    let p_vals: Vec<f64> = partials.iter().map(|x| x.p).collect();
+    let fdrs = stat::adjust(&p_vals, config.fdr_method);
+    partials
+        .into_iter()
+        .zip(fdrs)
+        .map(|(row, fdr)| MHGResult { fdr, ..row })
+        .collect()
+}
+
+/// Hypergeometric tail probability of at least `b` set analytes in the top `n` of a list of
+/// length `big_n` with `k` set analytes
+fn hypergeometric_tail(big_n: usize, k: usize, n: usize, b: usize) -> f64 {
+    let top = n.min(k);
+    if b > top {
+        return 0.0;
+    }
+    let ln_total = ln_binomial(big_n as u64, n as u64);
+    let p: f64 = (b..=top)
+        .filter(|x| n - x <= big_n - k)
+        .map(|x| {
+            (ln_binomial(k as u64, x as u64) + ln_binomial((big_n - k) as u64, (n - x) as u64)
+                - ln_total)
+                .exp()
+        })
+        .sum();
+    p.min(1.0)
+}
+
+/// Returns the mHG statistic with the cutoff and overlap where it was found
+fn mhg_statistic(hits: &[bool], k: usize, config: &MHGConfig) -> (f64, usize, usize) {
+    let big_n = hits.len();
+    let max_cutoff = config.max_cutoff.unwrap_or(big_n).min(big_n);
+    let mut best = (1.0, 0, 0);
+    let mut b = 0;
+    for (i, hit) in hits.iter().take(max_cutoff).enumerate() {
+        if !hit {
+            continue;
+        }
+        // the tail probability for a number of hits is smallest directly after a hit
+        b += 1;
+        if b < config.min_hits {
+            continue;
+        }
+        let tail = hypergeometric_tail(big_n, k, i + 1, b);
+        if tail < best.0 {
+            best = (tail, i + 1, b);
+        }
+    }
+    best
+}
+
+/// Exact p-value of the mHG statistic. Walks all paths from `(0, 0)` to `(big_n, k)` and sums
+/// the probability of the paths that reach a cutoff at least as extreme as `mhg`.
+fn mhg_p(big_n: usize, k: usize, mhg: f64, config: &MHGConfig) -> f64 {
+    if mhg >= 1.0 {
+        return 1.0;
+    }
+    let max_cutoff = config.max_cutoff.unwrap_or(big_n).min(big_n);
+    let threshold = mhg * (1.0 + 1e-10);
+    // probability of being at (n, b) without having reached the rejection region
+    let mut current = vec![0.0; k + 1];
+    let mut next = vec![0.0; k + 1];
+    current[0] = 1.0;
+    let mut p = 0.0;
+    for n in 0..max_cutoff {
+        // smallest number of hits at n + 1 in the rejection region
+        let mut region_start = k + 1;
+        let mut tail = 0.0;
+        let ln_total = ln_binomial(big_n as u64, (n + 1) as u64);
+        for b in (config.min_hits.max(1)..=(n + 1).min(k)).rev() {
+            if n + 1 - b > big_n - k {
+                break;
+            }
+            tail += (ln_binomial(k as u64, b as u64)
+                + ln_binomial((big_n - k) as u64, (n + 1 - b) as u64)
+                - ln_total)
+                .exp();
+            if tail > threshold {
+                break;
+            }
+            region_start = b;
+        }
+        next.iter_mut().for_each(|x| *x = 0.0);
+        let remaining = (big_n - n) as f64;
+        for b in 0..=n.min(k) {
+            let prob = current[b];
+            if prob == 0.0 {
+                continue;
+            }
+            let p_hit = (k - b) as f64 / remaining;
+            if b < k {
+                if b + 1 >= region_start {
+                    p += prob * p_hit;
+                } else {
+                    next[b + 1] += prob * p_hit;
+                }
+            }
+            if b >= region_start {
+                p += prob * (1.0 - p_hit);
+            } else {
+                next[b] += prob * (1.0 - p_hit);
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+    }
+    p.min(1.0)
+}
@@ -2,7 +2,7 @@ use ahash::{AHashMap, AHashSet};
 use statrs::distribution::{Continuous, ContinuousCDF, Normal};
 
 use super::{
-    gsea::{GSEAConfig, GSEAResult, RankListItem},
+    gsea::{GSEAConfig, GSEAResult, RankListItem, WeightingScheme},
     ora::{get_ora, ORAConfig, ORAResult},
 };
 use crate::{
@@ -74,6 +74,7 @@ pub fn multilist_gsea(
     if let MultiListMethod::Meta(meta_method) = method {
         let mut phash: AHashMap<String, Vec<f64>> = AHashMap::default();
         let mut results: Vec<Vec<GSEAResult>> = Vec::new();
+        let scheme = WeightingScheme::from_p(jobs.first().map_or(1.0, |x| x.config.p));
         for job in jobs {
             let res = gsea(job.rank_list, job.gmt, job.config, None);
             for row in res.iter() {
@@ -106,7 +107,15 @@ pub fn multilist_gsea(
                 nes: 0.0,
                 es: 0.0,
                 running_sum: Vec::new(),
+                running_sum_positions: Vec::new(),
                 leading_edge: 0,
+                leading_edge_analytes: Vec::new(),
+                rank_at_max: 0,
+                tags: 0.0,
+                list: 0.0,
+                signal: 0.0,
+                scheme,
+                seed: None,
             })
         }
         results.insert(0, final_result);
@@ -177,6 +186,12 @@ pub fn multilist_ora(
                     overlap: 0,
                     expected: 0.0,
                     enrichment_ratio: 0.0,
+                    set_size: 0,
+                    reference_set_size: 0,
+                    overlap_ids: Vec::new(),
+                    odds_ratio: 0.0,
+                    odds_ratio_ci_low: 0.0,
+                    odds_ratio_ci_high: 0.0,
                 })
             }
             results.insert(0, final_result);
@@ -257,9 +272,9 @@ fn mean_combine(
     final_list
 }
 
-fn normalize(list: &mut Vec<RankListItem>, method: NormalizationMethod) -> Vec<RankListItem> {
+fn normalize(list: &mut [RankListItem], method: NormalizationMethod) -> Vec<RankListItem> {
     match method {
-        NormalizationMethod::None => list.clone(),
+        NormalizationMethod::None => list.to_vec(),
         NormalizationMethod::MedianRank => {
             list.sort_by(|a, b| {
                 a.rank
@@ -356,17 +371,17 @@ pub fn combine_gmts(gmts: &Vec<Vec<Item>>) -> Vec<Item> {
 /// let vals: Vec<f64> = vec![0.1, 0.01, 0.11, 0.23];
 /// let metap: f64 = stouffer(&vals);
 /// ```
-pub fn stouffer(vals: &Vec<f64>) -> f64 {
+pub fn stouffer(vals: &[f64]) -> f64 {
     let n = Normal::new(0.0, 1.0).unwrap();
     stouffer_with_normal(vals, &n)
 }
 
-fn stouffer_with_normal(vals: &Vec<f64>, normal: &Normal) -> f64 {
+fn stouffer_with_normal(vals: &[f64], normal: &Normal) -> f64 {
     let k = vals.len();
     normal.cdf(vals.iter().map(|x| normal.inverse_cdf(*x)).sum::<f64>() / f64::sqrt(k as f64))
 }
 
-pub fn fisher(vals: &Vec<f64>) -> f64 {
+pub fn fisher(vals: &[f64]) -> f64 {
     let k = vals.len();
     let pt = -2.0 * vals.iter().map(|x| x.ln()).sum::<f64>();
     let dist = statrs::distribution::ChiSquared::new(2_f64.powi(k as i32 - 1)).unwrap();
@@ -1,11 +1,14 @@
-use ndarray::{Array2, Axis, Zip};
+use crate::methods::ora::{get_ora, ORAConfig, ORAResult};
+use crate::readers::utils::Item;
+use ahash::AHashSet;
+use rayon::prelude::*;
 use serde::Serialize;
-use std::ops::Div;
 
 #[derive(Debug, Clone)]
 /// A struct representing the options for the NTA algorithm
 pub struct NTAConfig {
-    /// A vector of vectors of strings representing the edge list of the graph
+    /// A vector of vectors of strings representing the edge list of the graph. Each edge is the
+    /// source, the target, and an optional positive weight (default: 1)
     pub edge_list: Vec<Vec<String>>,
     /// A vector of strings representing the seeds
     pub seeds: Vec<String>,
@@ -15,6 +18,9 @@ pub struct NTAConfig {
     pub tolerance: f64,
     /// The [`NTAMethod`] to use for the analysis
     pub method: Option<NTAMethod>,
+    /// Only walk edges from the source to the target, instead of in both directions
+    /// (default: false)
+    pub directed: bool,
 }
 
 /// Different methods for the NTA method that decides the important nodes to return
@@ -34,6 +40,7 @@ impl Default for NTAConfig {
             reset_probability: 0.5,
             tolerance: 0.000001,
             method: None,
+            directed: false,
         }
     }
 }
@@ -81,11 +88,11 @@ pub fn get_nta(config: NTAConfig) -> NTAResult {
                     candidates.push(node.clone());
                 }
             }
-            return NTAResult {
+            NTAResult {
                 neighborhood,
                 scores,
                 candidates,
-            };
+            }
         }
         Some(NTAMethod::Expand(size)) => {
             nta_res = nta_res
@@ -100,11 +107,11 @@ pub fn get_nta(config: NTAConfig) -> NTAResult {
                 scores.push(*score);
             }
             let candidates: Vec<String> = Vec::new();
-            return NTAResult {
+            NTAResult {
                 neighborhood,
                 scores,
                 candidates,
-            };
+            }
         }
         _ => {
             panic!("Invalid method");
@@ -112,31 +119,92 @@ pub fn get_nta(config: NTAConfig) -> NTAResult {
     }
 }
 
+/// Struct representing the NTA results with the enrichment of the neighborhood
+#[derive(Debug, Serialize)]
+pub struct NTAEnrichmentResult {
+    /// The [`NTAResult`] of the network analysis
+    pub network: NTAResult,
+    /// The [`ORAResult`] of every tested analyte set
+    pub enrichment: Vec<ORAResult>,
+}
+
+/// Performs network topology-based analysis, followed by over-representation analysis of the
+/// neighborhood, as in WebGestaltR
+///
+/// ## Parameters
+///
+/// - `config`: A [`NTAConfig`] struct containing the parameters for the network analysis.
+/// - `gmt`: A [`Vec<Item>`] of the gmt file
+/// - `ora_config`: The [`ORAConfig`] of the enrichment analysis
+///
+/// ## Returns
+///
+/// Returns a [`NTAEnrichmentResult`] with the [`NTAResult`] and the ORA of
+/// [`NTAResult::neighborhood`], using every node of the network as the reference list.
+pub fn get_nta_enrichment(
+    config: NTAConfig,
+    gmt: Vec<Item>,
+    ora_config: ORAConfig,
+) -> NTAEnrichmentResult {
+    let reference: AHashSet<String> = config
+        .edge_list
+        .iter()
+        .flat_map(|edge| edge.iter().take(2).cloned())
+        .collect();
+    let network = get_nta(config);
+    let interest: AHashSet<String> = network.neighborhood.iter().cloned().collect();
+    let enrichment = get_ora(&interest, &reference, gmt, ora_config);
+    NTAEnrichmentResult {
+        network,
+        enrichment,
+    }
+}
+
 /// Uses random walk to calculate the probabilities of each node being walked through
 /// Returns [`Vec<String>`] representing the nodes in the neighborhood
 ///
+/// Each step leaves a node through its outgoing edges in proportion to their weights. In directed
+/// mode, nodes without outgoing edges do not pass their probability on.
+///
 /// ## Parameters
 /// - `config` - A [`NTAConfig`] struct containing the edge list, seeds, neighborhood size, reset probability, and tolerance
 ///
+/// ## Panics
+///
+/// Panics if an edge has fewer than two nodes, or a weight that is not a positive number.
+///
 /// ## Returns
 ///
 /// Returns a [`Vec<(String, f64)>`] where the [`String`] is the original node name, and the following value is the random walk probability (higher is typically better)
 pub fn process_nta(config: NTAConfig) -> Vec<(String, f64)> {
     println!("Building Graph");
-    let unique_nodes = ahash::AHashSet::from_iter(config.edge_list.iter().flatten().cloned());
     let mut node_map: ahash::AHashMap<String, usize> = ahash::AHashMap::default();
-    let mut reverse_map: ahash::AHashMap<usize, String> = ahash::AHashMap::default();
-    for (i, node) in unique_nodes.iter().enumerate() {
-        node_map.insert(node.clone(), i);
-        reverse_map.insert(i, node.clone());
-    }
-    let mut graph = Array2::<f64>::zeros((unique_nodes.len(), unique_nodes.len()));
-    for edge in config.edge_list.iter() {
-        let node1 = node_map.get(&edge[0]).unwrap();
-        let node2 = node_map.get(&edge[1]).unwrap();
-        graph[[*node1, *node2]] = 1.0;
-        graph[[*node2, *node1]] = 1.0;
+    let mut nodes: Vec<String> = Vec::new();
+    // the third column is the weight, not a node
+    for node in config.edge_list.iter().flat_map(|edge| edge.iter().take(2)) {
+        if !node_map.contains_key(node) {
+            node_map.insert(node.clone(), nodes.len());
+            nodes.push(node.clone());
+        }
     }
+    let edges: Vec<(usize, usize, f64)> = config
+        .edge_list
+        .iter()
+        .map(|edge| {
+            if edge.len() < 2 {
+                panic!("Edge {:?} does not have a source and a target", edge);
+            }
+            let weight = match edge.get(2) {
+                Some(weight) => match weight.parse::<f64>() {
+                    Ok(weight) if weight > 0.0 && weight.is_finite() => weight,
+                    _ => panic!("Edge {:?} does not have a positive weight", edge),
+                },
+                None => 1.0,
+            };
+            (node_map[&edge[0]], node_map[&edge[1]], weight)
+        })
+        .collect();
+    let graph = TransitionMatrix::from_edges(nodes.len(), &edges, config.directed);
     println!("Calculating NTA");
     let node_indices: Vec<usize> = config
         .seeds
@@ -151,49 +219,117 @@ pub fn process_nta(config: NTAConfig) -> Vec<(String, f64)> {
     );
     let mut walk = walk_res.iter().enumerate().collect::<Vec<(usize, &f64)>>();
     walk.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
-    walk.iter()
-        .map(|(i, p)| (reverse_map.get(&i).unwrap().clone(), **p))
-        .collect()
+    walk.iter().map(|(i, p)| (nodes[*i].clone(), **p)).collect()
+}
+
+/// Column-normalized weighted adjacency matrix of a graph in compressed sparse row (CSR)
+/// format. Entry `(i, j)` is the weight of the edge from `j` to `i` divided by the weighted
+/// out-degree of `j`, so multiplying it by the walk probabilities takes one step of the random
+/// walk.
+struct TransitionMatrix {
+    /// Start of every row in `columns` and `values`, with the total number of entries at the end
+    row_starts: Vec<usize>,
+    /// Column of every entry, sorted within each row
+    columns: Vec<usize>,
+    /// Value of every entry
+    values: Vec<f64>,
+}
+
+impl TransitionMatrix {
+    /// Build the matrix from weighted `(source, target, weight)` edges between node indices below
+    /// `size`. Undirected edges are walked in both directions. Repeated edges count once, with
+    /// their largest weight.
+    fn from_edges(size: usize, edges: &[(usize, usize, f64)], directed: bool) -> TransitionMatrix {
+        // incoming edges of every node as (source, weight)
+        let mut incoming: Vec<Vec<(usize, f64)>> = vec![Vec::new(); size];
+        for (source, target, weight) in edges {
+            incoming[*target].push((*source, *weight));
+            if !directed {
+                incoming[*source].push((*target, *weight));
+            }
+        }
+        for row in incoming.iter_mut() {
+            row.sort_unstable_by(|a, b| a.0.cmp(&b.0).then(b.1.partial_cmp(&a.1).unwrap()));
+            row.dedup_by_key(|x| x.0);
+        }
+        let mut out_degrees = vec![0.0; size];
+        for (source, weight) in incoming.iter().flatten() {
+            out_degrees[*source] += weight;
+        }
+        let mut row_starts = Vec::with_capacity(size + 1);
+        row_starts.push(0);
+        let mut columns = Vec::new();
+        let mut values = Vec::new();
+        for row in incoming {
+            for (source, weight) in row {
+                columns.push(source);
+                values.push(weight / out_degrees[source]);
+            }
+            row_starts.push(columns.len());
+        }
+        TransitionMatrix {
+            row_starts,
+            columns,
+            values,
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.row_starts.len() - 1
+    }
+
+    /// Multiply the matrix by the vector `x`
+    fn dot(&self, x: &[f64]) -> Vec<f64> {
+        (0..self.size())
+            .into_par_iter()
+            .map(|i| {
+                let row = self.row_starts[i]..self.row_starts[i + 1];
+                self.columns[row.clone()]
+                    .iter()
+                    .zip(&self.values[row])
+                    .map(|(j, value)| value * x[*j])
+                    .sum()
+            })
+            .collect()
+    }
 }
 
 /// calculates the probability each node will be walked when starting from the one of the seeds
 ///
 /// ## Parameters
 ///
-/// - `adj_matrix` - A 2d adjacency matrix, where 1 means the node at the row and column indices are connected
+/// - `transition` - The sparse [`TransitionMatrix`] of the graph
 /// - `seed_indices` - a [`Vec<usize>`] of the indices of the seeds (starting points)
 /// - `r` - a [`f64`] of the reset probability (default in WebGestaltR is 0.5)
 /// - `tolerance` - the tolerance/threshold value in [`f64`] (WebGestaltR default is `1e-6`)
 ///
 /// ## Output
 ///
-/// Returns 1d array containing the probability for each node
+/// Returns the probability for each node
 fn random_walk_probability(
-    adj_matrix: &ndarray::Array2<f64>,
+    transition: &TransitionMatrix,
     seed_indices: &Vec<usize>,
     r: f64,
     tolerance: f64,
-) -> ndarray::Array1<f64> {
+) -> Vec<f64> {
     let num_nodes = seed_indices.len() as f64;
-    let de = adj_matrix.sum_axis(Axis(0));
-    // de to 2d array
-    let de = de.insert_axis(Axis(1));
-    let temp = adj_matrix.t().div(de);
-    let w = temp.t();
-    let mut p0 = ndarray::Array1::from_elem(w.shape()[0], 0.0);
+    let mut p0 = vec![0.0; transition.size()];
     for i in seed_indices {
         p0[*i] = 1.0 / num_nodes;
     }
+    let step = |pt: &[f64]| -> Vec<f64> {
+        transition
+            .dot(pt)
+            .iter()
+            .zip(&p0)
+            .map(|(walked, reset)| walked * (1.0 - r) + r * reset)
+            .collect()
+    };
     let mut pt = p0.clone();
-    let mut pt1 = w.dot(&pt) * (1.0 - r) + (r * &p0);
-    while Zip::from(&pt1)
-        .and(&pt)
-        .par_map_collect(|a, b| (a - b).abs())
-        .sum()
-        > tolerance
-    {
+    let mut pt1 = step(&pt);
+    while pt1.iter().zip(&pt).map(|(a, b)| (a - b).abs()).sum::<f64>() > tolerance {
         pt = pt1;
-        pt1 = w.dot(&pt) * (1.0 - r) + (r * &p0);
+        pt1 = step(&pt);
     }
     pt1
 }
@@ -0,0 +1,271 @@
+use crate::{readers::utils::Item, stat};
+use ahash::{AHashMap, AHashSet};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use super::ora::{get_ora_with_excluded, ora_p_directed, ORAConfig, ORAResult};
+
+/// Algorithm used to decorrelate the GO graph structure, following
+/// [topGO](https://doi.org/10.1093/bioinformatics/btl140)
+#[derive(Debug, Clone, Copy)]
+pub enum DecorrelationMethod {
+    /// Remove the analytes of significant terms from all of their ancestors
+    Elim,
+    /// Down-weight the analytes shared by a term and its children, depending on which of them is
+    /// more significant
+    Weight,
+}
+
+/// Parameters for ontology-aware ORA
+#[derive(Clone)]
+pub struct OntologyORAConfig {
+    /// The [`ORAConfig`] used for the classic analysis and the set filters
+    pub ora: ORAConfig,
+    /// The [`DecorrelationMethod`] to use
+    pub method: DecorrelationMethod,
+    /// p-value cutoff for a term to be considered significant by the elim algorithm
+    pub elim_cutoff: f64,
+}
+
+impl Default for OntologyORAConfig {
+    fn default() -> Self {
+        OntologyORAConfig {
+            ora: ORAConfig::default(),
+            method: DecorrelationMethod::Elim,
+            elim_cutoff: 0.01,
+        }
+    }
+}
+
+/// ORA result with both the classic and the decorrelated p-values
+#[derive(Debug, Serialize, Clone)]
+pub struct OntologyORAResult {
+    /// The classic ORA result, treating terms as independent
+    #[serde(flatten)]
+    pub classic: ORAResult,
+    /// The p-value after decorrelating the graph structure
+    pub decorrelated_p: f64,
+    /// The FDR of the decorrelated p-values
+    pub decorrelated_fdr: f64,
+}
+
+/// Run ORA that accounts for the hierarchy of the ontology terms (such as GO).
+///
+/// The GMT is expected to have propagated annotations, so each term contains the analytes of its
+/// descendants. Only the sets that pass the filters of the [`ORAConfig`] are decorrelated and
+/// reported.
+///
+/// # Parameters
+///
+/// - `interest_list` - A [`AHashSet<String>`] of the interesting analytes
+/// - `reference` - A [`AHashSet<String>`] of the reference list
+/// - `gmt` - A [`Vec<Item>`] of the gmt file
+/// - `hierarchy` - Slice of `(child, parent)` term pairs. Terms not in `gmt` are used to connect
+///   terms but are not tested. Terms without any pair keep their classic p-value
+/// - `config` - The [`OntologyORAConfig`] for the analysis
+///
+/// # Returns
+///
+/// Returns a [`Vec<OntologyORAResult>`] of the tested sets
+pub fn get_ontology_ora(
+    interest_list: &AHashSet<String>,
+    reference: &AHashSet<String>,
+    gmt: Vec<Item>,
+    hierarchy: &[(String, String)],
+    config: OntologyORAConfig,
+) -> Vec<OntologyORAResult> {
+    let parts: AHashMap<String, Vec<String>> = gmt
+        .iter()
+        .map(|item| (item.id.clone(), item.parts.clone()))
+        .collect();
+    let (classic, _) = get_ora_with_excluded(interest_list, reference, gmt, config.ora.clone());
+    let tested: AHashSet<&str> = classic.iter().map(|x| x.set.as_str()).collect();
+    let mut parents: AHashMap<&str, Vec<&str>> = AHashMap::default();
+    let mut children: AHashMap<&str, Vec<&str>> = AHashMap::default();
+    for (child, parent) in hierarchy.iter() {
+        parents.entry(child).or_default().push(parent);
+        children.entry(parent).or_default().push(child);
+    }
+    let order: Vec<&str> = bottom_up_order(&parents, &children)
+        .into_iter()
+        .filter(|x| tested.contains(x))
+        .collect();
+    let term_test = TermTest {
+        interest_list,
+        reference,
+        m: reference.len() as i64,
+        n: interest_list.len() as i64,
+        config: &config.ora,
+    };
+    let decorrelated: AHashMap<&str, f64> = match config.method {
+        DecorrelationMethod::Elim => elim(&order, &parts, &parents, &term_test, config.elim_cutoff),
+        DecorrelationMethod::Weight => weight(&order, &parts, &children, &tested, &term_test),
+    };
+    let decorrelated_p: Vec<f64> = classic
+        .iter()
+        .map(|x| decorrelated.get(x.set.as_str()).copied().unwrap_or(x.p))
+        .collect();
+    let decorrelated_fdr = stat::adjust(&decorrelated_p, config.ora.fdr_method);
+    classic
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| OntologyORAResult {
+            classic: row,
+            decorrelated_p: decorrelated_p[i],
+            decorrelated_fdr: decorrelated_fdr[i],
+        })
+        .collect()
+}
+
+struct TermTest<'a> {
+    interest_list: &'a AHashSet<String>,
+    reference: &'a AHashSet<String>,
+    m: i64,
+    n: i64,
+    config: &'a ORAConfig,
+}
+
+impl TermTest<'_> {
+    /// p-value of a term using only the analytes with a weight. Without weights, every analyte
+    /// counts as one. Weighted counts are rounded to the nearest integer.
+    fn p(&self, parts: &[String], weights: Option<&AHashMap<&str, f64>>) -> f64 {
+        let mut j: f64 = 0.0;
+        let mut k: f64 = 0.0;
+        for analyte in parts.iter() {
+            if !self.reference.contains(analyte) {
+                continue;
+            }
+            let w = weights.map_or(1.0, |x| *x.get(analyte.as_str()).unwrap_or(&1.0));
+            j += w;
+            if self.interest_list.contains(analyte) {
+                k += w;
+            }
+        }
+        ora_p_directed(
+            self.m,
+            j.round() as i64,
+            self.n,
+            k.round() as i64,
+            self.config.direction,
+        )
+    }
+}
+
+/// Order the terms so every term comes after all of its descendants
+fn bottom_up_order<'a>(
+    parents: &AHashMap<&'a str, Vec<&'a str>>,
+    children: &AHashMap<&'a str, Vec<&'a str>>,
+) -> Vec<&'a str> {
+    let mut remaining: AHashMap<&str, usize> = AHashMap::default();
+    let mut queue: VecDeque<&str> = VecDeque::new();
+    for term in parents.keys().chain(children.keys()) {
+        if remaining.contains_key(term) {
+            continue;
+        }
+        let count = children.get(term).map_or(0, |x| x.len());
+        remaining.insert(term, count);
+        if count == 0 {
+            queue.push_back(term);
+        }
+    }
+    let mut order: Vec<&str> = Vec::new();
+    while let Some(term) = queue.pop_front() {
+        order.push(term);
+        for parent in parents.get(term).into_iter().flatten() {
+            let count = remaining.get_mut(parent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                queue.push_back(parent);
+            }
+        }
+    }
+    order
+}
+
+fn ancestors<'a>(term: &str, parents: &AHashMap<&'a str, Vec<&'a str>>) -> AHashSet<&'a str> {
+    let mut found: AHashSet<&str> = AHashSet::default();
+    let mut stack: Vec<&str> = parents.get(term).cloned().unwrap_or_default();
+    while let Some(parent) = stack.pop() {
+        if found.insert(parent) {
+            stack.extend(parents.get(parent).into_iter().flatten());
+        }
+    }
+    found
+}
+
+/// elim algorithm. Terms are tested from the bottom of the graph, and the analytes of every
+/// significant term are removed from all of its ancestors.
+fn elim<'a>(
+    order: &[&'a str],
+    parts: &AHashMap<String, Vec<String>>,
+    parents: &AHashMap<&'a str, Vec<&'a str>>,
+    term_test: &TermTest,
+    cutoff: f64,
+) -> AHashMap<&'a str, f64> {
+    let mut removed: AHashMap<&str, AHashSet<String>> = AHashMap::default();
+    let mut p_vals: AHashMap<&str, f64> = AHashMap::default();
+    for term in order.iter() {
+        let remaining: Vec<String> = match removed.get(term) {
+            Some(removed) => parts[*term]
+                .iter()
+                .filter(|x| !removed.contains(*x))
+                .cloned()
+                .collect(),
+            None => parts[*term].clone(),
+        };
+        let p = term_test.p(&remaining, None);
+        if p < cutoff {
+            for ancestor in ancestors(term, parents) {
+                removed
+                    .entry(ancestor)
+                    .or_default()
+                    .extend(remaining.iter().cloned());
+            }
+        }
+        p_vals.insert(term, p);
+    }
+    p_vals
+}
+
+/// weight algorithm. Each term is compared with its children. If a child is more significant,
+/// the shared analytes are down-weighted in the term by the ratio of the p-values. Otherwise the
+/// analytes of the child are down-weighted in the child, and the child is scored again.
+fn weight<'a>(
+    order: &[&'a str],
+    parts: &'a AHashMap<String, Vec<String>>,
+    children: &AHashMap<&'a str, Vec<&'a str>>,
+    tested: &AHashSet<&str>,
+    term_test: &TermTest,
+) -> AHashMap<&'a str, f64> {
+    let mut weights: AHashMap<&str, AHashMap<&str, f64>> = AHashMap::default();
+    let mut p_vals: AHashMap<&str, f64> = AHashMap::default();
+    for term in order.iter() {
+        let mut term_weights: AHashMap<&str, f64> = AHashMap::default();
+        let mut p = term_test.p(&parts[*term], None);
+        for child in children.get(term).into_iter().flatten() {
+            if !tested.contains(child) {
+                continue;
+            }
+            let child_p = p_vals[child];
+            if child_p < p {
+                let ratio = if p > 0.0 { child_p / p } else { 1.0 };
+                for analyte in parts[*child].iter() {
+                    *term_weights.entry(analyte.as_str()).or_insert(1.0) *= ratio;
+                }
+            } else {
+                let ratio = if child_p > 0.0 { p / child_p } else { 1.0 };
+                let child_weights = weights.entry(child).or_default();
+                for analyte in parts[*child].iter() {
+                    *child_weights.entry(analyte.as_str()).or_insert(1.0) *= ratio;
+                }
+                p_vals.insert(child, term_test.p(&parts[*child], Some(child_weights)));
+            }
+        }
+        if !term_weights.is_empty() {
+            p = term_test.p(&parts[*term], Some(&term_weights));
+        }
+        weights.insert(term, term_weights);
+        p_vals.insert(term, p);
+    }
+    p_vals
+}
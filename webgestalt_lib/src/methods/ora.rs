@@ -1,8 +1,12 @@
 use crate::{readers::utils::Item, stat};
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
 use rayon::prelude::*;
 use serde::Serialize;
-use statrs::distribution::{DiscreteCDF, Hypergeometric};
+use statrs::distribution::{ContinuousCDF, Discrete, DiscreteCDF, Hypergeometric, Normal};
+
+pub mod empirical;
+pub mod library;
+use library::{AnalyteBitSet, LibrarySet, ORALibrary};
 
 #[derive(Clone)]
 pub struct ORAConfig {
@@ -10,6 +14,24 @@ pub struct ORAConfig {
     pub min_set_size: usize,
     pub max_set_size: usize,
     pub fdr_method: stat::AdjustmentMethod,
+    /// Whether to report the overlapping analytes of each set. Disable for a compact output on
+    /// very large runs
+    pub report_overlap_ids: bool,
+    /// The [`ORATestDirection`] of the test. The default `min_overlap` leaves out depleted sets
+    /// with few overlapping analytes, so use [`ORAConfig::for_direction`] to test for depletion
+    pub direction: ORATestDirection,
+}
+
+/// Direction of the ORA test
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ORATestDirection {
+    /// Test for over-representation (upper tail of the hypergeometric distribution)
+    Over,
+    /// Test for depletion (lower tail of the hypergeometric distribution)
+    Under,
+    /// Two-sided Fisher's exact test
+    TwoSided,
 }
 
 impl Default for ORAConfig {
@@ -19,6 +41,24 @@ impl Default for ORAConfig {
             min_set_size: 5,
             max_set_size: 500,
             fdr_method: stat::AdjustmentMethod::BH,
+            report_overlap_ids: true,
+            direction: ORATestDirection::Over,
+        }
+    }
+}
+
+impl ORAConfig {
+    /// Default parameters for a test direction. Depletion and two-sided tests have a
+    /// `min_overlap` of `0`, as a depleted set can have no overlap with the interest list
+    pub fn for_direction(direction: ORATestDirection) -> ORAConfig {
+        let min_overlap = match direction {
+            ORATestDirection::Over => ORAConfig::default().min_overlap,
+            ORATestDirection::Under | ORATestDirection::TwoSided => 0,
+        };
+        ORAConfig {
+            min_overlap,
+            direction,
+            ..Default::default()
         }
     }
 }
@@ -31,6 +71,43 @@ pub struct ORAResult {
     pub overlap: i64,
     pub expected: f64,
     pub enrichment_ratio: f64,
+    /// Number of analytes in the set as given in the GMT
+    pub set_size: i64,
+    /// Number of analytes in the set after intersecting with the reference list
+    pub reference_set_size: i64,
+    /// The analytes of the set found in the interest list. Empty if
+    /// [`ORAConfig::report_overlap_ids`] is `false`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overlap_ids: Vec<String>,
+    /// Sample odds ratio of the 2x2 contingency table
+    pub odds_ratio: f64,
+    /// Lower bound of the 95% confidence interval of the odds ratio
+    pub odds_ratio_ci_low: f64,
+    /// Upper bound of the 95% confidence interval of the odds ratio
+    pub odds_ratio_ci_high: f64,
+}
+
+/// Reason an analyte set was left out of the ORA before FDR adjustment
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ORAExclusionReason {
+    /// Fewer analytes of the set are in the reference than [`ORAConfig::min_set_size`]
+    SetTooSmall,
+    /// More analytes of the set are in the reference than [`ORAConfig::max_set_size`]
+    SetTooLarge,
+    /// Overlap with the interest list is below [`ORAConfig::min_overlap`]
+    OverlapTooSmall,
+}
+
+/// An analyte set that was not tested, with the reason it was excluded
+#[derive(Debug, Serialize, Clone)]
+pub struct ORAExcludedSet {
+    pub set: String,
+    /// Number of analytes of the set in the interest list
+    pub overlap: i64,
+    /// Number of analytes of the set in the reference list
+    pub set_size: i64,
+    pub reason: ORAExclusionReason,
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +116,10 @@ struct PartialORAResult {
     p: f64,
     overlap: i64,
     expected: f64,
+    set_size: i64,
+    reference_set_size: i64,
+    overlap_ids: Vec<String>,
+    odds_ratio: OddsRatio,
 }
 
 pub fn ora_p(m: i64, j: i64, n: i64, k: i64) -> f64 {
@@ -46,9 +127,97 @@ pub fn ora_p(m: i64, j: i64, n: i64, k: i64) -> f64 {
     result.sf((k - 1) as u64)
 }
 
+/// Calculate the p-value of observing an overlap of `k` in the given [`ORATestDirection`].
+///
+/// # Parameters
+/// - `m` - Size of the reference list
+/// - `j` - Size of the analyte set in the reference list
+/// - `n` - Size of the interest list
+/// - `k` - Overlap of the analyte set and the interest list
+pub fn ora_p_directed(m: i64, j: i64, n: i64, k: i64, direction: ORATestDirection) -> f64 {
+    match direction {
+        ORATestDirection::Over => {
+            if k == 0 {
+                1.0
+            } else {
+                ora_p(m, j, n, k)
+            }
+        }
+        ORATestDirection::Under => {
+            let result = Hypergeometric::new(m as u64, j as u64, n as u64).unwrap();
+            result.cdf(k as u64)
+        }
+        ORATestDirection::TwoSided => fisher_exact_two_sided(m, j, n, k),
+    }
+}
+
+/// Two-sided Fisher's exact test. Sums the probability of every table that is at most as likely
+/// as the observed one, with the same relative tolerance as R's `fisher.test`.
+fn fisher_exact_two_sided(m: i64, j: i64, n: i64, k: i64) -> f64 {
+    let dist = Hypergeometric::new(m as u64, j as u64, n as u64).unwrap();
+    let observed = dist.pmf(k as u64);
+    let threshold = observed * (1.0 + 1e-7);
+    let low = (n + j - m).max(0) as u64;
+    let high = j.min(n) as u64;
+    let p: f64 = (low..=high)
+        .map(|x| dist.pmf(x))
+        .filter(|x| *x <= threshold)
+        .sum();
+    p.min(1.0)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OddsRatio {
+    estimate: f64,
+    low: f64,
+    high: f64,
+}
+
+/// Sample odds ratio of the contingency table with a Woolf (logit) 95% confidence interval.
+/// Adds 0.5 to every cell if any cell is zero (Haldane-Anscombe correction).
+fn odds_ratio(m: i64, j: i64, n: i64, k: i64) -> OddsRatio {
+    let mut a = k as f64;
+    let mut b = (n - k) as f64;
+    let mut c = (j - k) as f64;
+    let mut d = (m - j - n + k) as f64;
+    if a == 0.0 || b == 0.0 || c == 0.0 || d == 0.0 {
+        a += 0.5;
+        b += 0.5;
+        c += 0.5;
+        d += 0.5;
+    }
+    let log_or = (a * d / (b * c)).ln();
+    let se = (1.0 / a + 1.0 / b + 1.0 / c + 1.0 / d).sqrt();
+    let z = Normal::new(0.0, 1.0).unwrap().inverse_cdf(0.975);
+    OddsRatio {
+        estimate: log_or.exp(),
+        low: (log_or - z * se).exp(),
+        high: (log_or + z * se).exp(),
+    }
+}
+
+impl ORAConfig {
+    /// Check the set size in the reference (`j`) and the overlap (`k`) against the config
+    /// filters. Returns the [`ORAExclusionReason`] if the set should not be tested.
+    pub fn exclusion_reason(&self, j: i64, k: i64) -> Option<ORAExclusionReason> {
+        if j < self.min_set_size as i64 {
+            Some(ORAExclusionReason::SetTooSmall)
+        } else if j > self.max_set_size as i64 {
+            Some(ORAExclusionReason::SetTooLarge)
+        } else if k < self.min_overlap {
+            Some(ORAExclusionReason::OverlapTooSmall)
+        } else {
+            None
+        }
+    }
+}
+
 /// Get ORA results for the provided interest list and reference list against the GMT file.
 /// Requires both the interest list and the reference list to be filtered.
 ///
+/// Sets that do not pass the size and overlap filters of `config` are not tested and are not
+/// counted in the FDR adjustment. Use [`get_ora_with_excluded`] to also get the excluded sets.
+///
 /// # Parameters
 /// - `interest_list` - A [`AHashSet<String>`] of the interesting analytes
 /// - `reference` - A [`AHashSet<String>`] of the reference list
@@ -59,36 +228,196 @@ pub fn get_ora(
     gmt: Vec<Item>,
     config: ORAConfig,
 ) -> Vec<ORAResult> {
+    get_ora_with_excluded(interest_list, reference, gmt, config).0
+}
+
+/// Get ORA results and the sets excluded by the filters in `config`.
+///
+/// # Parameters
+/// - `interest_list` - A [`AHashSet<String>`] of the interesting analytes
+/// - `reference` - A [`AHashSet<String>`] of the reference list
+/// - `gmt` - A [`Vec<Item>`] of the gmt file
+///
+/// # Returns
+///
+/// Returns a tuple of the [`Vec<ORAResult>`] of the tested sets and a [`Vec<ORAExcludedSet>`]
+/// of the sets that were filtered out before FDR adjustment
+pub fn get_ora_with_excluded(
+    interest_list: &AHashSet<String>,
+    reference: &AHashSet<String>,
+    gmt: Vec<Item>,
+    config: ORAConfig,
+) -> (Vec<ORAResult>, Vec<ORAExcludedSet>) {
+    let library = ORALibrary::new(&gmt);
+    let interest_list = library.bitset(interest_list);
+    let reference = library.bitset(reference);
+    get_ora_prepared(&library, &interest_list, &reference, config)
+}
+
+/// Get ORA results for a prepared [`ORALibrary`]. Use this instead of [`get_ora_with_excluded`]
+/// to reuse the library for several interest lists.
+///
+/// # Parameters
+/// - `library` - The [`ORALibrary`] of the gmt file
+/// - `interest_list` - An [`AnalyteBitSet`] of the interesting analytes
+/// - `reference` - An [`AnalyteBitSet`] of the reference list
+///
+/// # Returns
+///
+/// Returns a tuple of the [`Vec<ORAResult>`] of the tested sets and a [`Vec<ORAExcludedSet>`]
+/// of the sets that were filtered out before FDR adjustment
+pub fn get_ora_prepared(
+    library: &ORALibrary,
+    interest_list: &AnalyteBitSet,
+    reference: &AnalyteBitSet,
+    config: ORAConfig,
+) -> (Vec<ORAResult>, Vec<ORAExcludedSet>) {
     let m: i64 = reference.len() as i64;
     let n: i64 = interest_list.len() as i64;
-    let partials: Vec<PartialORAResult> = gmt
+    let direction = config.direction;
+    ora_with_p_fn(library, interest_list, reference, config, |_, _, j, k| {
+        ora_p_directed(m, j, n, k, direction)
+    })
+}
+
+/// Run ORA for many interest lists against the same library and reference list. The reference
+/// is only prepared once, and the lists and sets are run in parallel. The FDR is adjusted
+/// separately for every list.
+///
+/// # Parameters
+/// - `library` - The [`ORALibrary`] of the gmt file
+/// - `interest_lists` - A [`AHashMap<String, AHashSet<String>>`] of the interest lists by name
+/// - `reference` - An [`AnalyteBitSet`] of the reference list
+///
+/// # Returns
+///
+/// Returns a [`AHashMap`] with the tuple of the tested sets and the excluded sets for every list
+/// name
+pub fn get_ora_batch(
+    library: &ORALibrary,
+    interest_lists: &AHashMap<String, AHashSet<String>>,
+    reference: &AnalyteBitSet,
+    config: ORAConfig,
+) -> AHashMap<String, (Vec<ORAResult>, Vec<ORAExcludedSet>)> {
+    let m: i64 = reference.len() as i64;
+    let reference_sizes = reference_set_sizes(library, reference);
+    let direction = config.direction;
+    interest_lists
         .par_iter()
-        .map(|i| {
-            let mut j: i64 = 0;
-            let mut enriched_parts: AHashSet<String> = AHashSet::default();
-            let mut k: i64 = 0;
-            for analyte in i.parts.iter() {
-                if interest_list.contains(analyte) {
-                    k += 1;
-                    enriched_parts.insert(analyte.to_owned());
-                }
-                if reference.contains(analyte) {
-                    j += 1;
-                }
+        .map(|(name, interest_list)| {
+            let interest_list = library.bitset(interest_list);
+            let n: i64 = interest_list.len() as i64;
+            let res = ora_with_reference_sizes(
+                library,
+                &interest_list,
+                m,
+                &reference_sizes,
+                config.clone(),
+                |_, _, j, k| ora_p_directed(m, j, n, k, direction),
+            );
+            (name.clone(), res)
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Shared ORA core. Counts the overlap of every set, applies the filters, and calls `p_fn` with
+/// the index of the set in [`ORALibrary::sets`], the [`LibrarySet`], the set size in the reference
+/// (`j`) and the overlap (`k`) to get the p-value of every tested set.
+pub(crate) fn ora_with_p_fn<F>(
+    library: &ORALibrary,
+    interest_list: &AnalyteBitSet,
+    reference: &AnalyteBitSet,
+    config: ORAConfig,
+    p_fn: F,
+) -> (Vec<ORAResult>, Vec<ORAExcludedSet>)
+where
+    F: Fn(usize, &LibrarySet, i64, i64) -> f64 + Sync,
+{
+    let reference_sizes = reference_set_sizes(library, reference);
+    ora_with_reference_sizes(
+        library,
+        interest_list,
+        reference.len() as i64,
+        &reference_sizes,
+        config,
+        p_fn,
+    )
+}
+
+/// Number of analytes of every set of the library in the reference list
+fn reference_set_sizes(library: &ORALibrary, reference: &AnalyteBitSet) -> Vec<i64> {
+    library
+        .sets()
+        .par_iter()
+        .map(|i| reference.count(&i.analytes) as i64)
+        .collect()
+}
+
+/// ORA core with the set sizes in the reference (`reference_sizes`) already counted
+fn ora_with_reference_sizes<F>(
+    library: &ORALibrary,
+    interest_list: &AnalyteBitSet,
+    m: i64,
+    reference_sizes: &[i64],
+    config: ORAConfig,
+    p_fn: F,
+) -> (Vec<ORAResult>, Vec<ORAExcludedSet>)
+where
+    F: Fn(usize, &LibrarySet, i64, i64) -> f64 + Sync,
+{
+    let n: i64 = interest_list.len() as i64;
+    let partials: Vec<Result<PartialORAResult, ORAExcludedSet>> = library
+        .sets()
+        .par_iter()
+        .zip(reference_sizes.par_iter())
+        .enumerate()
+        .map(|(index, (i, j))| {
+            let j = *j;
+            let k = interest_list.count(&i.analytes) as i64;
+            if let Some(reason) = config.exclusion_reason(j, k) {
+                return Err(ORAExcludedSet {
+                    set: i.id.clone(),
+                    overlap: k,
+                    set_size: j,
+                    reason,
+                });
             }
-            let p = if k == 0 { 1.0 } else { ora_p(m, j, n, k) };
-            PartialORAResult {
+            let enriched_parts: Vec<String> = if config.report_overlap_ids {
+                i.analytes
+                    .iter()
+                    .filter(|x| interest_list.contains(**x))
+                    .map(|x| library.name(*x).to_owned())
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let p = p_fn(index, i, j, k);
+            Ok(PartialORAResult {
                 set: i.id.clone(),
                 p,
                 overlap: k,
                 expected: j as f64 * n as f64 / m as f64,
-            }
+                set_size: i.size as i64,
+                reference_set_size: j,
+                overlap_ids: enriched_parts,
+                odds_ratio: odds_ratio(m, j, n, k),
+            })
         })
         .collect();
-    let p_vals: Vec<f64> = partials.iter().map(|x| x.p).collect();
+    let mut tested: Vec<PartialORAResult> = Vec::new();
+    let mut excluded: Vec<ORAExcludedSet> = Vec::new();
+    for partial in partials {
+        match partial {
+            Ok(row) => tested.push(row),
+            Err(row) => excluded.push(row),
+        }
+    }
+    let p_vals: Vec<f64> = tested.iter().map(|x| x.p).collect();
     let fdrs: Vec<f64> = stat::adjust(&p_vals, config.fdr_method);
     let mut final_res = Vec::new();
-    for (i, row) in partials.clone().into_iter().enumerate() {
+    for (i, row) in tested.into_iter().enumerate() {
         final_res.push(ORAResult {
             set: row.set,
             p: row.p,
@@ -96,7 +425,13 @@ pub fn get_ora(
             overlap: row.overlap,
             expected: row.expected,
             enrichment_ratio: row.overlap as f64 / row.expected,
+            set_size: row.set_size,
+            reference_set_size: row.reference_set_size,
+            overlap_ids: row.overlap_ids,
+            odds_ratio: row.odds_ratio.estimate,
+            odds_ratio_ci_low: row.odds_ratio.low,
+            odds_ratio_ci_high: row.odds_ratio.high,
         })
     }
-    final_res
+    (final_res, excluded)
 }
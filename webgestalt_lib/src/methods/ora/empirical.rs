@@ -0,0 +1,267 @@
+use crate::readers::utils::Item;
+use ahash::{AHashMap, AHashSet};
+use rand::{seq::index::sample, SeedableRng};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use super::{
+    library::ORALibrary, ora_with_p_fn, ORAConfig, ORAExcludedSet, ORAResult, ORATestDirection,
+};
+
+/// Number of random interest lists drawn at once before checking which sets can stop
+const CHUNK_SIZE: usize = 1000;
+
+/// Parameters for ORA with empirical p-values
+#[derive(Clone)]
+pub struct EmpiricalORAConfig {
+    /// The [`ORAConfig`] used for the set filters, test direction, and FDR
+    pub ora: ORAConfig,
+    /// Maximum number of random interest lists
+    pub max_resamples: usize,
+    /// Stop resampling a set once this many random lists are at least as extreme as the interest
+    /// list ([Besag and Clifford, 1991](https://doi.org/10.1093/biomet/78.2.301)). Use `0` to
+    /// always draw `max_resamples` lists
+    pub min_exceedances: usize,
+    /// Number of covariate bins for matched sampling. Only used if a covariate is provided
+    pub bins: usize,
+    /// Seed of the random number generator
+    pub seed: u64,
+}
+
+impl Default for EmpiricalORAConfig {
+    fn default() -> Self {
+        EmpiricalORAConfig {
+            ora: ORAConfig::default(),
+            max_resamples: 10000,
+            min_exceedances: 10,
+            bins: 10,
+            seed: 0,
+        }
+    }
+}
+
+/// ORA result with an empirical p-value
+#[derive(Debug, Serialize, Clone)]
+pub struct EmpiricalORAResult {
+    /// The ORA result, where `p` and `fdr` are from resampling
+    #[serde(flatten)]
+    pub result: ORAResult,
+    /// Number of random interest lists used for the p-value of the set
+    pub resamples: usize,
+}
+
+/// Resampling state of one set
+#[derive(Clone, Default)]
+struct SetState {
+    positions: Vec<u32>,
+    observed: usize,
+    upper: usize,
+    lower: usize,
+    resamples: usize,
+    stopped: bool,
+    /// The set is filtered out by the [`ORAConfig`] and never resampled
+    excluded: bool,
+}
+
+impl SetState {
+    fn exceedances(&self, direction: ORATestDirection) -> usize {
+        match direction {
+            ORATestDirection::Over => self.upper,
+            ORATestDirection::Under => self.lower,
+            ORATestDirection::TwoSided => self.upper.min(self.lower),
+        }
+    }
+
+    fn p(&self, direction: ORATestDirection) -> f64 {
+        let p = |count: usize| {
+            if self.stopped {
+                count as f64 / self.resamples as f64
+            } else {
+                (count + 1) as f64 / (self.resamples + 1) as f64
+            }
+        };
+        match direction {
+            ORATestDirection::Over => p(self.upper),
+            ORATestDirection::Under => p(self.lower),
+            ORATestDirection::TwoSided => (2.0 * p(self.upper).min(p(self.lower))).min(1.0),
+        }
+    }
+}
+
+/// Run ORA with p-values estimated by sampling random interest lists of the same size from the
+/// reference list.
+///
+/// If a `covariate` is provided, the reference is split into [`EmpiricalORAConfig::bins`] bins of
+/// equal size by covariate, and every random list has the same number of analytes from each bin
+/// as the interest list. Analytes without a covariate form their own bin.
+///
+/// Every random list is seeded from [`EmpiricalORAConfig::seed`] and its index, so results do not
+/// depend on the number of threads.
+///
+/// # Parameters
+///
+/// - `interest_list` - A [`AHashSet<String>`] of the interesting analytes
+/// - `reference` - A [`AHashSet<String>`] of the reference list
+/// - `gmt` - A [`Vec<Item>`] of the gmt file
+/// - `covariate` - Optional [`AHashMap<String, f64>`] of a covariate of each analyte for matched
+///   sampling
+/// - `config` - The [`EmpiricalORAConfig`] of the analysis
+///
+/// # Returns
+///
+/// Returns a tuple of the [`Vec<EmpiricalORAResult>`] of the tested sets and a
+/// [`Vec<ORAExcludedSet>`] of the sets that were filtered out before FDR adjustment
+pub fn get_ora_empirical(
+    interest_list: &AHashSet<String>,
+    reference: &AHashSet<String>,
+    gmt: Vec<Item>,
+    covariate: Option<&AHashMap<String, f64>>,
+    config: EmpiricalORAConfig,
+) -> (Vec<EmpiricalORAResult>, Vec<ORAExcludedSet>) {
+    let direction = config.ora.direction;
+    let library = ORALibrary::new(&gmt);
+    let interest_bits = library.bitset(interest_list);
+    let reference_bits = library.bitset(reference);
+    let mut analytes: Vec<&String> = reference.iter().collect();
+    analytes.sort();
+    let positions: AHashMap<&str, u32> = analytes
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.as_str(), i as u32))
+        .collect();
+    let mut states: Vec<SetState> = library
+        .sets()
+        .iter()
+        .map(|set| {
+            let set_positions: Vec<u32> = set
+                .analytes
+                .iter()
+                .filter_map(|x| positions.get(library.name(*x)).copied())
+                .collect();
+            let observed = interest_bits.count(&set.analytes);
+            let excluded = config
+                .ora
+                .exclusion_reason(set_positions.len() as i64, observed as i64)
+                .is_some();
+            SetState {
+                positions: set_positions,
+                observed,
+                stopped: excluded,
+                excluded,
+                ..Default::default()
+            }
+        })
+        .collect();
+    let strata = make_strata(&analytes, interest_list, covariate, config.bins);
+    let word_count = (analytes.len() + 63) / 64;
+    let mut drawn = 0;
+    while drawn < config.max_resamples && states.iter().any(|x| !x.stopped) {
+        let chunk_end = (drawn + CHUNK_SIZE).min(config.max_resamples);
+        let samples: Vec<Vec<u64>> = (drawn..chunk_end)
+            .into_par_iter()
+            .map(|r| {
+                let mut rng =
+                    rand::rngs::SmallRng::seed_from_u64(config.seed.wrapping_add(r as u64));
+                let mut words = vec![0_u64; word_count];
+                for (stratum, draws) in strata.iter() {
+                    for i in sample(&mut rng, stratum.len(), *draws).iter() {
+                        let position = stratum[i];
+                        words[position as usize / 64] |= 1 << (position % 64);
+                    }
+                }
+                words
+            })
+            .collect();
+        states
+            .par_iter_mut()
+            .filter(|x| !x.stopped)
+            .for_each(|state| {
+                for words in samples.iter() {
+                    let overlap = state
+                        .positions
+                        .iter()
+                        .filter(|x| words[**x as usize / 64] & (1 << (**x % 64)) != 0)
+                        .count();
+                    if overlap >= state.observed {
+                        state.upper += 1;
+                    }
+                    if overlap <= state.observed {
+                        state.lower += 1;
+                    }
+                    state.resamples += 1;
+                    if config.min_exceedances > 0
+                        && state.exceedances(direction) >= config.min_exceedances
+                    {
+                        state.stopped = true;
+                        break;
+                    }
+                }
+            });
+        drawn = chunk_end;
+    }
+    let (results, excluded) = ora_with_p_fn(
+        &library,
+        &interest_bits,
+        &reference_bits,
+        config.ora,
+        |index, _, _, _| states[index].p(direction),
+    );
+    // the tested sets are returned in the order of the library
+    let results = results
+        .into_iter()
+        .zip(states.iter().filter(|x| !x.excluded))
+        .map(|(result, state)| EmpiricalORAResult {
+            resamples: state.resamples,
+            result,
+        })
+        .collect();
+    (results, excluded)
+}
+
+/// Split the reference positions into strata, with the number of interesting analytes in each.
+/// Without a covariate, the whole reference is one stratum.
+fn make_strata(
+    analytes: &[&String],
+    interest_list: &AHashSet<String>,
+    covariate: Option<&AHashMap<String, f64>>,
+    bins: usize,
+) -> Vec<(Vec<u32>, usize)> {
+    let count = |stratum: &[u32]| {
+        stratum
+            .iter()
+            .filter(|x| interest_list.contains(analytes[**x as usize]))
+            .count()
+    };
+    let covariate = match covariate {
+        Some(covariate) => covariate,
+        None => {
+            let all: Vec<u32> = (0..analytes.len() as u32).collect();
+            let draws = count(&all);
+            return vec![(all, draws)];
+        }
+    };
+    let mut with_covariate: Vec<(u32, f64)> = Vec::new();
+    let mut without_covariate: Vec<u32> = Vec::new();
+    for (i, analyte) in analytes.iter().enumerate() {
+        match covariate.get(*analyte) {
+            Some(value) if value.is_finite() => with_covariate.push((i as u32, *value)),
+            _ => without_covariate.push(i as u32),
+        }
+    }
+    with_covariate.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+    let bin_size = ((with_covariate.len() + bins.max(1) - 1) / bins.max(1)).max(1);
+    let mut strata: Vec<Vec<u32>> = with_covariate
+        .chunks(bin_size)
+        .map(|x| x.iter().map(|(i, _)| *i).collect())
+        .collect();
+    if !without_covariate.is_empty() {
+        strata.push(without_covariate);
+    }
+    strata
+        .into_iter()
+        .map(|stratum| {
+            let draws = count(&stratum);
+            (stratum, draws)
+        })
+        .collect()
+}
@@ -0,0 +1,121 @@
+use crate::readers::utils::Item;
+use ahash::{AHashMap, AHashSet};
+
+/// A GMT prepared for ORA. Every analyte is interned into an integer ID once, and every set is
+/// stored as a sorted vector of IDs.
+#[derive(Debug, Clone)]
+pub struct ORALibrary {
+    names: Vec<String>,
+    index: AHashMap<String, u32>,
+    sets: Vec<LibrarySet>,
+}
+
+/// An analyte set of an [`ORALibrary`]
+#[derive(Debug, Clone)]
+pub struct LibrarySet {
+    /// The set name
+    pub id: String,
+    /// The set URL
+    pub url: String,
+    /// Sorted IDs of the unique analytes in the set
+    pub analytes: Vec<u32>,
+    /// Number of analytes in the set as given in the GMT
+    pub size: usize,
+}
+
+/// A list of analytes (such as an interest or reference list) as a bitset over the IDs of an
+/// [`ORALibrary`]
+#[derive(Debug, Clone)]
+pub struct AnalyteBitSet {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl ORALibrary {
+    /// Intern the analytes of `gmt` and convert its sets to ID vectors
+    pub fn new(gmt: &[Item]) -> ORALibrary {
+        let mut names: Vec<String> = Vec::new();
+        let mut index: AHashMap<String, u32> = AHashMap::default();
+        let sets = gmt
+            .iter()
+            .map(|item| {
+                let mut analytes: Vec<u32> = item
+                    .parts
+                    .iter()
+                    .map(|analyte| {
+                        *index.entry(analyte.clone()).or_insert_with(|| {
+                            names.push(analyte.clone());
+                            (names.len() - 1) as u32
+                        })
+                    })
+                    .collect();
+                analytes.sort_unstable();
+                analytes.dedup();
+                LibrarySet {
+                    id: item.id.clone(),
+                    url: item.url.clone(),
+                    analytes,
+                    size: item.parts.len(),
+                }
+            })
+            .collect();
+        ORALibrary { names, index, sets }
+    }
+
+    /// The sets of the library, in the order of the GMT
+    pub fn sets(&self) -> &[LibrarySet] {
+        &self.sets
+    }
+
+    /// The analyte name of an ID
+    pub fn name(&self, id: u32) -> &str {
+        &self.names[id as usize]
+    }
+
+    /// The ID of an analyte, if it is in any set of the library
+    pub fn id(&self, analyte: &str) -> Option<u32> {
+        self.index.get(analyte).copied()
+    }
+
+    /// Number of unique analytes in the library
+    pub fn analyte_count(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Convert a list of analytes to an [`AnalyteBitSet`]. Analytes not in the library are not
+    /// stored but still count towards [`AnalyteBitSet::len`].
+    pub fn bitset(&self, analytes: &AHashSet<String>) -> AnalyteBitSet {
+        let mut words = vec![0_u64; (self.names.len() + 63) / 64];
+        for analyte in analytes.iter() {
+            if let Some(id) = self.id(analyte) {
+                words[id as usize / 64] |= 1 << (id % 64);
+            }
+        }
+        AnalyteBitSet {
+            words,
+            len: analytes.len(),
+        }
+    }
+}
+
+impl AnalyteBitSet {
+    /// Whether the analyte with the ID is in the list
+    pub fn contains(&self, id: u32) -> bool {
+        self.words[id as usize / 64] & (1 << (id % 64)) != 0
+    }
+
+    /// Number of the IDs in `ids` that are in the list
+    pub fn count(&self, ids: &[u32]) -> usize {
+        ids.iter().filter(|id| self.contains(**id)).count()
+    }
+
+    /// Number of analytes in the original list, including ones not in the library
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the original list was empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
@@ -0,0 +1,294 @@
+use crate::readers::utils::Item;
+use ahash::{AHashMap, AHashSet};
+use serde::Serialize;
+
+use super::{gsea::GSEAResult, ora::ORAResult};
+
+/// Method used to collapse redundant analyte sets
+#[derive(Debug, Clone, Copy)]
+pub enum RedundancyMethod {
+    /// Affinity propagation on the Jaccard similarity of the set members. The exemplar of each
+    /// cluster is its representative
+    AffinityPropagation,
+    /// Greedy weighted set cover of the analytes in the sets, weighted by significance. Selects at
+    /// most the provided [`usize`] number of sets. Sets sharing no analytes with a selected set
+    /// form their own cluster
+    WeightedSetCover(usize),
+}
+
+/// A representative analyte set and the redundant sets it stands for
+#[derive(Debug, Serialize, Clone)]
+pub struct RedundancyCluster {
+    /// The set chosen to represent the cluster
+    pub representative: String,
+    /// All sets in the cluster, including the representative
+    pub members: Vec<String>,
+}
+
+/// An enrichment result that can be used for redundancy reduction
+pub trait EnrichedSet {
+    /// The set name, matching the `id` of an [`Item`] in the GMT
+    fn set_id(&self) -> &str;
+    /// The statistical p-value of the set
+    fn p_value(&self) -> f64;
+}
+
+impl EnrichedSet for ORAResult {
+    fn set_id(&self) -> &str {
+        &self.set
+    }
+    fn p_value(&self) -> f64 {
+        self.p
+    }
+}
+
+impl EnrichedSet for GSEAResult {
+    fn set_id(&self) -> &str {
+        &self.set
+    }
+    fn p_value(&self) -> f64 {
+        self.p
+    }
+}
+
+/// Collapse redundant analyte sets into clusters with a representative set.
+///
+/// Only the provided results are clustered, so they should already be filtered to the significant
+/// sets. Sets are compared by their members in the GMT.
+///
+/// # Parameters
+///
+/// - `results` - Slice of enrichment results, such as [`ORAResult`] or [`GSEAResult`]
+/// - `gmt` - Slice of [`Item`] of the GMT used for the analysis
+/// - `method` - The [`RedundancyMethod`] to use
+///
+/// # Returns
+///
+/// Returns a [`Vec<RedundancyCluster>`] sorted by the p-value of the representative set. Every
+/// provided set is in exactly one cluster
+pub fn reduce_redundancy<T: EnrichedSet>(
+    results: &[T],
+    gmt: &[Item],
+    method: RedundancyMethod,
+) -> Vec<RedundancyCluster> {
+    if results.is_empty() {
+        return Vec::new();
+    }
+    let parts: AHashMap<&str, AHashSet<&String>> = gmt
+        .iter()
+        .map(|item| (item.id.as_str(), item.parts.iter().collect()))
+        .collect();
+    let empty: AHashSet<&String> = AHashSet::default();
+    let members: Vec<&AHashSet<&String>> = results
+        .iter()
+        .map(|x| parts.get(x.set_id()).unwrap_or(&empty))
+        .collect();
+    let scores: Vec<f64> = results
+        .iter()
+        .map(|x| -x.p_value().max(f64::MIN_POSITIVE).log10())
+        .collect();
+    let assignments = match method {
+        RedundancyMethod::AffinityPropagation => affinity_propagation(&members, &scores),
+        RedundancyMethod::WeightedSetCover(top_n) => weighted_set_cover(&members, &scores, top_n),
+    };
+    let mut clusters: AHashMap<usize, Vec<usize>> = AHashMap::default();
+    for (i, exemplar) in assignments.iter().enumerate() {
+        clusters.entry(*exemplar).or_default().push(i);
+    }
+    let mut exemplars: Vec<usize> = clusters.keys().copied().collect();
+    exemplars.sort_by(|a, b| {
+        results[*a]
+            .p_value()
+            .partial_cmp(&results[*b].p_value())
+            .unwrap()
+            .then(a.cmp(b))
+    });
+    exemplars
+        .into_iter()
+        .map(|exemplar| RedundancyCluster {
+            representative: results[exemplar].set_id().to_owned(),
+            members: clusters[&exemplar]
+                .iter()
+                .map(|i| results[*i].set_id().to_owned())
+                .collect(),
+        })
+        .collect()
+}
+
+fn jaccard(a: &AHashSet<&String>, b: &AHashSet<&String>) -> f64 {
+    let intersection = a.intersection(b).count();
+    let union = a.len() + b.len() - intersection;
+    if union == 0 {
+        0.0
+    } else {
+        intersection as f64 / union as f64
+    }
+}
+
+/// Affinity propagation ([DOI:10.1126/science.1136800](https://doi.org/10.1126/science.1136800))
+/// with the median similarity as preference, nudged by the significance of each set. Uses the
+/// defaults of the R `apcluster` package.
+///
+/// Returns the index of the exemplar for every set.
+fn affinity_propagation(members: &[&AHashSet<&String>], scores: &[f64]) -> Vec<usize> {
+    const DAMPING: f64 = 0.9;
+    const MAX_ITERATIONS: usize = 1000;
+    const CONVERGENCE_ITERATIONS: usize = 100;
+    let n = members.len();
+    if n == 1 {
+        return vec![0];
+    }
+    let mut s = vec![0.0; n * n];
+    let mut off_diagonal: Vec<f64> = Vec::with_capacity(n * (n - 1));
+    for i in 0..n {
+        for k in 0..n {
+            if i != k {
+                let sim = jaccard(members[i], members[k]);
+                s[i * n + k] = sim;
+                off_diagonal.push(sim);
+            }
+        }
+    }
+    off_diagonal.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = off_diagonal.len() / 2;
+    let preference = if off_diagonal.len() % 2 == 0 {
+        (off_diagonal[mid - 1] + off_diagonal[mid]) / 2.0
+    } else {
+        off_diagonal[mid]
+    };
+    // break ties between equally similar sets in favour of the more significant ones
+    let max_score = scores.iter().copied().fold(f64::MIN_POSITIVE, f64::max);
+    for i in 0..n {
+        s[i * n + i] = preference + 1e-6 * scores[i] / max_score;
+    }
+    let mut r = vec![0.0; n * n];
+    let mut a = vec![0.0; n * n];
+    let mut exemplars: Vec<usize> = Vec::new();
+    let mut unchanged = 0;
+    for _ in 0..MAX_ITERATIONS {
+        // update responsibilities
+        for i in 0..n {
+            let mut first = f64::NEG_INFINITY;
+            let mut second = f64::NEG_INFINITY;
+            let mut first_k = 0;
+            for k in 0..n {
+                let val = a[i * n + k] + s[i * n + k];
+                if val > first {
+                    second = first;
+                    first = val;
+                    first_k = k;
+                } else if val > second {
+                    second = val;
+                }
+            }
+            for k in 0..n {
+                let max_other = if k == first_k { second } else { first };
+                let new_r = s[i * n + k] - max_other;
+                r[i * n + k] = DAMPING * r[i * n + k] + (1.0 - DAMPING) * new_r;
+            }
+        }
+        // update availabilities
+        for k in 0..n {
+            let positive_sum: f64 = (0..n)
+                .filter(|i| *i != k)
+                .map(|i| r[i * n + k].max(0.0))
+                .sum();
+            for i in 0..n {
+                let new_a = if i == k {
+                    positive_sum
+                } else {
+                    (r[k * n + k] + positive_sum - r[i * n + k].max(0.0)).min(0.0)
+                };
+                a[i * n + k] = DAMPING * a[i * n + k] + (1.0 - DAMPING) * new_a;
+            }
+        }
+        let new_exemplars: Vec<usize> = (0..n)
+            .filter(|k| a[k * n + k] + r[k * n + k] > 0.0)
+            .collect();
+        if new_exemplars == exemplars && !exemplars.is_empty() {
+            unchanged += 1;
+            if unchanged >= CONVERGENCE_ITERATIONS {
+                break;
+            }
+        } else {
+            unchanged = 0;
+            exemplars = new_exemplars;
+        }
+    }
+    if exemplars.is_empty() {
+        // no exemplar emerged, so use the most significant set for everything
+        let best = (0..n)
+            .max_by(|x, y| scores[*x].partial_cmp(&scores[*y]).unwrap().then(y.cmp(x)))
+            .unwrap();
+        exemplars.push(best);
+    }
+    (0..n)
+        .map(|i| {
+            if exemplars.contains(&i) {
+                i
+            } else {
+                exemplars
+                    .iter()
+                    .copied()
+                    .max_by(|x, y| {
+                        s[i * n + x]
+                            .partial_cmp(&s[i * n + y])
+                            .unwrap()
+                            .then(y.cmp(x))
+                    })
+                    .unwrap()
+            }
+        })
+        .collect()
+}
+
+/// Greedy weighted set cover, where the cost of each set is the inverse of its significance.
+/// Sets are selected until every analyte is covered or `top_n` sets are selected.
+///
+/// Returns the index of the selected set with the largest Jaccard similarity for every set.
+/// Sets sharing no analytes with any selected set are their own exemplar, so they are kept as
+/// clusters of one set.
+fn weighted_set_cover(members: &[&AHashSet<&String>], scores: &[f64], top_n: usize) -> Vec<usize> {
+    let mut uncovered: AHashSet<&String> = members.iter().flat_map(|x| x.iter().copied()).collect();
+    let mut selected: Vec<usize> = Vec::new();
+    while !uncovered.is_empty() && selected.len() < top_n {
+        let mut best: Option<(usize, f64)> = None;
+        for (i, set) in members.iter().enumerate() {
+            if selected.contains(&i) || scores[i] <= 0.0 {
+                continue;
+            }
+            let newly_covered = set.iter().filter(|x| uncovered.contains(*x)).count();
+            if newly_covered == 0 {
+                continue;
+            }
+            let cost = 1.0 / (scores[i] * newly_covered as f64);
+            if best.map_or(true, |(_, best_cost)| cost < best_cost) {
+                best = Some((i, cost));
+            }
+        }
+        match best {
+            Some((i, _)) => {
+                for analyte in members[i].iter() {
+                    uncovered.remove(analyte);
+                }
+                selected.push(i);
+            }
+            None => break,
+        }
+    }
+    (0..members.len())
+        .map(|i| {
+            if selected.contains(&i) {
+                return i;
+            }
+            let mut best: Option<(usize, f64)> = None;
+            for k in selected.iter() {
+                let sim = jaccard(members[i], members[*k]);
+                if sim > 0.0 && best.map_or(true, |(_, best_sim)| sim > best_sim) {
+                    best = Some((*k, sim));
+                }
+            }
+            best.map_or(i, |(k, _)| k)
+        })
+        .collect()
+}
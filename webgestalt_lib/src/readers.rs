@@ -5,7 +5,7 @@ use std::{
     fs::File,
     io::{prelude::*, BufReader},
 };
-use utils::Item;
+use utils::{ExpressionMatrix, Item, Phenotype};
 
 /// Read GMT file from specified path. For format description, see [broadinstitute.org](https://software.broadinstitute.org/cancer/software/gsea/wiki/index.php/Data_formats#GMT:_Gene_Matrix_Transposed_file_format_.28.2A.gmt.29)
 ///
@@ -43,6 +43,232 @@ pub fn read_gmt_file(path: String) -> Result<Vec<Item>, Box<std::io::Error>> {
     Ok(items)
 }
 
+/// Read the term hierarchy of an ontology from specified path. Each line has a child term and a
+/// parent term separated by a tab.
+///
+/// # Parameters
+///
+/// - `path` - A [`String`] of the path of the hierarchy to read.
+///
+/// # Returns
+///
+/// If result is `Ok`, returns a [`Vec<(String, String)>`] of the `(child, parent)` pairs. Returns
+/// an error of kind [`std::io::ErrorKind::InvalidData`] if a line does not have two columns.
+pub fn read_hierarchy_file(path: String) -> Result<Vec<(String, String)>, Box<std::io::Error>> {
+    let file = File::open(path)?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .flexible(true)
+        .has_headers(false)
+        .from_reader(file);
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    for r in rdr.records() {
+        let record = r.map_err(|e| invalid_data(format!("Invalid hierarchy file: {}", e)))?;
+        match (record.get(0), record.get(1)) {
+            (Some(child), Some(parent)) => pairs.push((child.to_owned(), parent.to_owned())),
+            _ => {
+                return Err(Box::new(invalid_data(format!(
+                    "Hierarchy file line must have two columns: {}",
+                    record.iter().collect::<Vec<&str>>().join("\t")
+                ))))
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+/// Read a GCT expression matrix from specified path. For format description, see [broadinstitute.org](https://software.broadinstitute.org/cancer/software/gsea/wiki/index.php/Data_formats#GCT:_Gene_Cluster_Text_file_format_.28.2A.gct.29)
+///
+/// # Parameters
+///
+/// - `path` - A [`String`] of the path of the GCT to read.
+///
+/// # Returns
+///
+/// If result is `Ok`, returns a [`ExpressionMatrix`] of the file. Returns an error of kind
+/// [`std::io::ErrorKind::InvalidData`] if the file is not a valid GCT file.
+pub fn read_gct_file(path: String) -> Result<ExpressionMatrix, Box<std::io::Error>> {
+    let file = File::open(path)?;
+    let mut lines = BufReader::new(file).lines();
+    let mut next_line = |what: &str| match lines.next() {
+        Some(line) => line,
+        None => Err(invalid_data(format!("GCT file is missing the {}", what))),
+    };
+    next_line("version line")?;
+    let dimensions = next_line("dimension line")?;
+    let dimensions: Vec<usize> = dimensions
+        .split_whitespace()
+        .map(|x| x.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| invalid_data(format!("Invalid GCT dimensions: {}", dimensions)))?;
+    if dimensions.len() != 2 {
+        return Err(Box::new(invalid_data(
+            "GCT dimension line must have two values".to_string(),
+        )));
+    }
+    let header = next_line("header line")?;
+    let samples: Vec<String> = header
+        .trim_end_matches(['\r', '\n'])
+        .split('\t')
+        .skip(2)
+        .map(|x| x.to_string())
+        .collect();
+    if samples.len() != dimensions[1] {
+        return Err(Box::new(invalid_data(format!(
+            "GCT header has {} samples, expected {}",
+            samples.len(),
+            dimensions[1]
+        ))));
+    }
+    let mut matrix = ExpressionMatrix {
+        analytes: Vec::new(),
+        descriptions: Vec::new(),
+        samples,
+        values: Vec::new(),
+    };
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        if parts.len() != dimensions[1] + 2 {
+            return Err(Box::new(invalid_data(format!(
+                "GCT row {} has {} values, expected {}",
+                parts[0],
+                parts.len().saturating_sub(2),
+                dimensions[1]
+            ))));
+        }
+        let values = parts[2..]
+            .iter()
+            .map(|x| x.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| invalid_data(format!("GCT row {} has a non-numeric value", parts[0])))?;
+        matrix.analytes.push(parts[0].to_string());
+        matrix.descriptions.push(parts[1].to_string());
+        matrix.values.push(values);
+    }
+    if matrix.values.len() != dimensions[0] {
+        return Err(Box::new(invalid_data(format!(
+            "GCT file has {} rows, expected {}",
+            matrix.values.len(),
+            dimensions[0]
+        ))));
+    }
+    Ok(matrix)
+}
+
+/// Read a CLS phenotype file from specified path. Both categorical and continuous (`#numeric`)
+/// files are supported. For continuous files, only the first phenotype is read. For format
+/// description, see [broadinstitute.org](https://software.broadinstitute.org/cancer/software/gsea/wiki/index.php/Data_formats#CLS:_Categorical_.28e.g_tumor_vs_normal.29_class_file_format_.28.2A.cls.29)
+///
+/// # Parameters
+///
+/// - `path` - A [`String`] of the path of the CLS to read.
+///
+/// # Returns
+///
+/// If result is `Ok`, returns the [`Phenotype`] of the samples. Returns an error of kind
+/// [`std::io::ErrorKind::InvalidData`] if the file is not a valid CLS file.
+pub fn read_cls_file(path: String) -> Result<Phenotype, Box<std::io::Error>> {
+    let file = File::open(path)?;
+    let lines: Vec<String> = BufReader::new(file)
+        .lines()
+        .collect::<Result<Vec<String>, _>>()?
+        .into_iter()
+        .filter(|x| !x.trim().is_empty())
+        .collect();
+    if lines.len() < 3 {
+        return Err(Box::new(invalid_data(
+            "CLS file must have at least three lines".to_string(),
+        )));
+    }
+    if lines[0].trim().eq_ignore_ascii_case("#numeric") {
+        let name = lines[1].trim().trim_start_matches('#').to_string();
+        let values = lines[2]
+            .split_whitespace()
+            .map(|x| x.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| invalid_data("CLS phenotype has a non-numeric value".to_string()))?;
+        return Ok(Phenotype::Continuous { name, values });
+    }
+    let header: Vec<usize> = lines[0]
+        .split_whitespace()
+        .map(|x| x.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| invalid_data(format!("Invalid CLS header: {}", lines[0])))?;
+    if header.len() < 2 {
+        return Err(Box::new(invalid_data(format!(
+            "Invalid CLS header: {}",
+            lines[0]
+        ))));
+    }
+    let mut classes: Vec<String> = lines[1]
+        .trim_start()
+        .trim_start_matches('#')
+        .split_whitespace()
+        .map(|x| x.to_string())
+        .collect();
+    let raw_labels: Vec<&str> = lines[2].split_whitespace().collect();
+    if raw_labels.len() != header[0] {
+        return Err(Box::new(invalid_data(format!(
+            "CLS file has {} labels, expected {}",
+            raw_labels.len(),
+            header[0]
+        ))));
+    }
+    // labels are class names, indices of the class names, or names in order of appearance
+    let labels: Vec<usize> = if raw_labels.iter().all(|x| classes.iter().any(|c| c == x)) {
+        raw_labels
+            .iter()
+            .map(|x| classes.iter().position(|c| c == x).unwrap())
+            .collect()
+    } else if let Some(indices) = raw_labels
+        .iter()
+        .map(|x| x.parse::<usize>().ok().filter(|i| *i < classes.len()))
+        .collect::<Option<Vec<usize>>>()
+    {
+        indices
+    } else {
+        classes.clear();
+        raw_labels
+            .iter()
+            .map(|x| match classes.iter().position(|c| c == x) {
+                Some(i) => i,
+                None => {
+                    classes.push(x.to_string());
+                    classes.len() - 1
+                }
+            })
+            .collect()
+    };
+    if classes.len() != header[1] {
+        return Err(Box::new(invalid_data(format!(
+            "CLS file has {} classes, expected {}",
+            classes.len(),
+            header[1]
+        ))));
+    }
+    Ok(Phenotype::Categorical { classes, labels })
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Read a tab-separated rank file of analytes and their ranks.
+///
+/// # Parameters
+///
+/// - `path` - A [`String`] of the path of the rank file to read.
+///
+/// # Returns
+///
+/// If result is `Ok`, returns a [`Vec<RankListItem>`] of the file. Ranks that are not numbers,
+/// such as `NA`, are read as NaN so [`crate::methods::gsea::prepare::prepare_rank_list`] can drop
+/// them or return an error. Returns an error of kind [`std::io::ErrorKind::InvalidData`] if a line
+/// does not have two columns.
 pub fn read_rank_file(path: String) -> Result<Vec<RankListItem>, Box<std::io::Error>> {
     let file = File::open(path)?;
     let mut rdr = csv::ReaderBuilder::new()
@@ -52,16 +278,19 @@ pub fn read_rank_file(path: String) -> Result<Vec<RankListItem>, Box<std::io::Er
         .from_reader(file);
     let mut items: Vec<RankListItem> = Vec::new();
     for r in rdr.records() {
-        let result = r
-            .unwrap()
-            .iter()
-            .map(|x| x.to_string())
-            .collect::<Vec<String>>();
-        let phenotype = result.first().unwrap().to_owned();
-        let rank = result.get(1).unwrap().to_owned().parse::<f64>().unwrap();
+        let record = r.map_err(|e| invalid_data(format!("Invalid rank file: {}", e)))?;
+        let (analyte, rank) = match (record.get(0), record.get(1)) {
+            (Some(analyte), Some(rank)) => (analyte, rank),
+            _ => {
+                return Err(Box::new(invalid_data(format!(
+                    "Rank file line must have two columns: {}",
+                    record.iter().collect::<Vec<&str>>().join("\t")
+                ))))
+            }
+        };
         let item = RankListItem {
-            analyte: phenotype,
-            rank,
+            analyte: analyte.to_owned(),
+            rank: rank.trim().parse::<f64>().unwrap_or(f64::NAN),
         };
         items.push(item);
     }
@@ -131,7 +360,8 @@ pub fn read_intersection_list(path: String, ref_list: &AHashSet<String>) -> AHas
     h
 }
 
-/// Read edge list from specified path. Separated by whitespace with no support for weights
+/// Read edge list from specified path. Separated by whitespace, with the source, the target, and
+/// an optional weight of every edge
 ///
 /// # Parameters
 /// path - A [`String`] of the path of the edge list to read.
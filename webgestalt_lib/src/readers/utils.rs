@@ -5,3 +5,35 @@ pub struct Item {
     pub url: String,
     pub parts: Vec<String>,
 }
+
+/// Expression matrix of a GCT file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpressionMatrix {
+    /// Name of the analyte of each row
+    pub analytes: Vec<String>,
+    /// Description of each row
+    pub descriptions: Vec<String>,
+    /// Name of each sample (column)
+    pub samples: Vec<String>,
+    /// Values of each row, with one value per sample
+    pub values: Vec<Vec<f64>>,
+}
+
+/// Phenotype of the samples from a CLS file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Phenotype {
+    /// Samples are split into classes
+    Categorical {
+        /// Name of each class
+        classes: Vec<String>,
+        /// Index in `classes` of the class of each sample
+        labels: Vec<usize>,
+    },
+    /// Samples have a numeric phenotype
+    Continuous {
+        /// Name of the phenotype
+        name: String,
+        /// Value of each sample
+        values: Vec<f64>,
+    },
+}
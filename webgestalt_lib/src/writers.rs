@@ -1,10 +1,8 @@
-/// Collection of utilities to save results to a file/folder
-
+//! Collection of utilities to save results to a file/folder
 
 use std::fs::File;
 use std::io::prelude::*;
 
-
 pub fn save_nta(
     path: String,
     result: crate::methods::nta::NTAResult,
@@ -13,4 +11,44 @@ pub fn save_nta(
     let json = serde_json::to_string(&result).unwrap();
     file.write_all(json.as_bytes())?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn save_nta_enrichment(
+    path: String,
+    result: &crate::methods::nta::NTAEnrichmentResult,
+) -> Result<(), Box<std::io::Error>> {
+    let mut file = File::create(path)?;
+    let json = serde_json::to_string(result).unwrap();
+    file.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+pub fn save_ora_excluded(
+    path: String,
+    excluded: &[crate::methods::ora::ORAExcludedSet],
+) -> Result<(), Box<std::io::Error>> {
+    let mut file = File::create(path)?;
+    let json = serde_json::to_string(excluded).unwrap();
+    file.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+pub fn save_gsea_excluded(
+    path: String,
+    excluded: &[crate::methods::gsea::GSEAExcludedSet],
+) -> Result<(), Box<std::io::Error>> {
+    let mut file = File::create(path)?;
+    let json = serde_json::to_string(excluded).unwrap();
+    file.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+pub fn save_gsea_plot_data(
+    path: String,
+    data: &crate::methods::gsea::EnrichmentPlotData,
+) -> Result<(), Box<std::io::Error>> {
+    let mut file = File::create(path)?;
+    let json = serde_json::to_string(data).unwrap();
+    file.write_all(json.as_bytes())?;
+    Ok(())
+}
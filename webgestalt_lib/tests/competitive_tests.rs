@@ -0,0 +1,122 @@
+use webgestalt_lib::methods::competitive::{get_competitive, CompetitiveConfig, CompetitiveMethod};
+use webgestalt_lib::methods::gsea::phenotype::{rank_metric, RankMetric};
+use webgestalt_lib::methods::gsea::RankListItem;
+use webgestalt_lib::readers::utils::Item;
+
+#[test]
+fn competitive_wilcoxon() {
+    // the set holds the top three of ten analytes
+    let rank_list: Vec<RankListItem> = (0..10)
+        .map(|i| RankListItem {
+            analyte: format!("g{}", i),
+            rank: (10 - i) as f64,
+        })
+        .collect();
+    let gmt = vec![Item {
+        id: String::from("top"),
+        url: String::new(),
+        parts: vec!["g0".to_string(), "g1".to_string(), "g2".to_string()],
+    }];
+    let config = CompetitiveConfig {
+        method: CompetitiveMethod::Wilcoxon,
+        min_set_size: 1,
+        ..Default::default()
+    };
+    let res = get_competitive(rank_list, gmt, config.clone(), None);
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].set_size, 3);
+    assert!((res[0].effect_size - 1.0).abs() < 1e-12);
+    // U = 21, mean 10.5, variance 3 * 7 * 11 / 12
+    let z = (21.0 - 10.5 - 0.5) / (3.0f64 * 7.0 * 11.0 / 12.0).sqrt();
+    assert!((res[0].statistic - z).abs() < 1e-12);
+    assert!(res[0].p < 0.05);
+    // ties share the mean rank and shrink the variance
+    let tied: Vec<RankListItem> = [3.0, 3.0, 2.0, 2.0, 1.0, 1.0]
+        .iter()
+        .enumerate()
+        .map(|(i, rank)| RankListItem {
+            analyte: format!("g{}", i),
+            rank: *rank,
+        })
+        .collect();
+    let gmt = vec![Item {
+        id: String::from("top"),
+        url: String::new(),
+        parts: vec!["g0".to_string(), "g1".to_string()],
+    }];
+    let res = get_competitive(tied, gmt, config, None);
+    // U = 5.5 + 5.5 - 3 = 8, mean 4, tie groups of 2 give 3 * (2^3 - 2) = 18
+    let variance = 2.0 * 4.0 / 12.0 * (7.0 - 18.0 / 30.0);
+    let z = (8.0 - 4.0 - 0.5) / f64::sqrt(variance);
+    assert!((res[0].statistic - z).abs() < 1e-12);
+}
+
+#[test]
+fn competitive_wilcoxon_balanced() {
+    // the set holds the top and bottom of ten analytes, so U equals its mean
+    let mut rank_list: Vec<RankListItem> = (0..10)
+        .map(|i| RankListItem {
+            analyte: format!("g{}", i),
+            rank: (10 - i) as f64,
+        })
+        .collect();
+    // analytes with NaN ranks are left out
+    rank_list.push(RankListItem {
+        analyte: String::from("missing"),
+        rank: f64::NAN,
+    });
+    let gmt = vec![Item {
+        id: String::from("balanced"),
+        url: String::new(),
+        parts: vec!["g0".to_string(), "g9".to_string(), "missing".to_string()],
+    }];
+    let config = CompetitiveConfig {
+        method: CompetitiveMethod::Wilcoxon,
+        min_set_size: 1,
+        ..Default::default()
+    };
+    let res = get_competitive(rank_list, gmt, config, None);
+    assert_eq!(res[0].set_size, 2);
+    assert_eq!(res[0].statistic, 0.0);
+    assert_eq!(res[0].effect_size, 0.0);
+    assert_eq!(res[0].p, 1.0);
+}
+
+#[test]
+fn competitive_methods() {
+    let matrix = webgestalt_lib::readers::read_gct_file("data/test.gct".to_string()).unwrap();
+    let phenotype = webgestalt_lib::readers::read_cls_file("data/test.cls".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<Item> = gmt.into_iter().take(6).collect();
+    let ranks = rank_metric(&matrix, &phenotype, RankMetric::Difference);
+    let methods = [
+        CompetitiveMethod::PAGE,
+        CompetitiveMethod::Wilcoxon,
+        CompetitiveMethod::Camera {
+            inter_gene_cor: Some(0.01),
+        },
+        CompetitiveMethod::Camera {
+            inter_gene_cor: None,
+        },
+    ];
+    for method in methods {
+        let config = CompetitiveConfig {
+            method,
+            ..Default::default()
+        };
+        let res = get_competitive(
+            ranks.clone(),
+            gmt.clone(),
+            config,
+            Some((&matrix, &phenotype)),
+        );
+        assert_eq!(res[0].set, "hsa00010");
+        assert!(res[0].statistic > 0.0 && res[0].effect_size > 0.0);
+        assert!(res[0].p < 0.01 && res[0].fdr < 0.05);
+        assert!(res[1].statistic < 0.0 && res[1].p < 0.01);
+        assert!(res.iter().all(|x| x.p <= x.fdr && x.fdr <= 1.0));
+        if method == CompetitiveMethod::PAGE {
+            assert!(res.iter().all(|x| x.correlation == 0.0));
+        }
+    }
+}
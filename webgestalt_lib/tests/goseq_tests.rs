@@ -0,0 +1,109 @@
+use ahash::{AHashMap, AHashSet};
+use statrs::assert_almost_eq;
+use webgestalt_lib::methods::goseq::{get_goseq, wallenius_pmf, GOSeqConfig, GOSeqMethod};
+use webgestalt_lib::methods::ora::{get_ora, ora_p, ORAConfig};
+use webgestalt_lib::readers::utils::Item;
+const THRESHOLD: f64 = 0.0001;
+
+#[test]
+fn wallenius_central_matches_hypergeometric() {
+    let probs = wallenius_pmf(20, 180, 30, 1.0);
+    let upper: f64 = probs.iter().skip(8).sum();
+    assert_almost_eq!(upper, ora_p(200, 20, 30, 8), THRESHOLD);
+}
+
+/// Long analytes are more likely to be interesting, and the set only contains long analytes
+fn biased_data() -> (
+    AHashSet<String>,
+    AHashSet<String>,
+    Vec<Item>,
+    AHashMap<String, f64>,
+) {
+    let reference: AHashSet<String> = (0..1000).map(|i| format!("g{}", i)).collect();
+    let covariate: AHashMap<String, f64> =
+        (0..1000).map(|i| (format!("g{}", i), i as f64)).collect();
+    // 1 in 20 short analytes and 1 in 4 long analytes are interesting
+    let interest: AHashSet<String> = (0..1000)
+        .filter(|i| if *i < 500 { i % 20 == 0 } else { i % 4 == 0 })
+        .map(|i| format!("g{}", i))
+        .collect();
+    let gmt = vec![Item {
+        id: "long".to_owned(),
+        url: String::new(),
+        parts: (800..900).map(|i| format!("g{}", i)).collect(),
+    }];
+    (interest, reference, gmt, covariate)
+}
+
+#[test]
+fn goseq_corrects_bias() {
+    let (interest, reference, gmt, covariate) = biased_data();
+    let classic = get_ora(&interest, &reference, gmt.clone(), ORAConfig::default());
+    let config = GOSeqConfig {
+        bin_size: 100,
+        ..Default::default()
+    };
+    let (wallenius, _) = get_goseq(
+        &interest,
+        &reference,
+        gmt.clone(),
+        &covariate,
+        config.clone(),
+    );
+    let (sampling, _) = get_goseq(
+        &interest,
+        &reference,
+        gmt,
+        &covariate,
+        GOSeqConfig {
+            method: GOSeqMethod::Sampling {
+                repeats: 2000,
+                seed: 1,
+            },
+            ..config
+        },
+    );
+    assert!(classic[0].p < 0.01);
+    assert!(wallenius[0].p > 0.1);
+    assert!(sampling[0].p > 0.1);
+}
+
+#[test]
+fn goseq_sampling_sets_with_same_id() {
+    let (interest, reference, _, covariate) = biased_data();
+    // the first set only has interesting analytes, the second has none
+    let gmt = vec![
+        Item {
+            id: "same".to_owned(),
+            url: String::new(),
+            parts: (800..900).step_by(4).map(|i| format!("g{}", i)).collect(),
+        },
+        Item {
+            id: "same".to_owned(),
+            url: String::new(),
+            parts: (800..900)
+                .filter(|i| i % 4 != 0)
+                .map(|i| format!("g{}", i))
+                .collect(),
+        },
+    ];
+    let repeats = 200;
+    let (sampling, _) = get_goseq(
+        &interest,
+        &reference,
+        gmt,
+        &covariate,
+        GOSeqConfig {
+            ora: ORAConfig {
+                min_overlap: 0,
+                ..Default::default()
+            },
+            method: GOSeqMethod::Sampling { repeats, seed: 1 },
+            bin_size: 100,
+        },
+    );
+    assert_eq!(sampling.len(), 2);
+    // no random list reaches the overlap of the first set, but the p-value is not zero
+    assert_almost_eq!(sampling[0].p, 1.0 / (repeats + 1) as f64, 1e-12);
+    assert_almost_eq!(sampling[1].p, 1.0, 1e-12);
+}
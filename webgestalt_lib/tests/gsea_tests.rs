@@ -1,9 +1,555 @@
 use pretty_assertions::assert_eq;
-use statrs::assert_almost_eq;
-const THRESHOLD: f64 = 0.0001;
 
 #[test]
 fn read_gmt() {
     let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
     assert_eq!(gmt.len(), 330)
 }
+
+#[test]
+fn gsea_weighting_schemes() {
+    use webgestalt_lib::methods::gsea::{gsea, GSEAConfig, WeightingScheme};
+    let rank_list = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(20).collect();
+    let run = |scheme: WeightingScheme| {
+        gsea(
+            rank_list.clone(),
+            gmt.clone(),
+            GSEAConfig {
+                p: scheme.p(),
+                permutations: 10,
+                ..Default::default()
+            },
+            None,
+        )
+    };
+    let classic = run(WeightingScheme::Classic);
+    let weighted = run(WeightingScheme::Weighted);
+    assert_eq!(classic[0].scheme, WeightingScheme::Classic);
+    assert_eq!(weighted[0].scheme, WeightingScheme::Weighted);
+    assert!(classic
+        .iter()
+        .zip(weighted.iter())
+        .any(|(a, b)| a.es != 0.0 && a.es != b.es));
+}
+
+#[test]
+fn gsea_seeded() {
+    use webgestalt_lib::methods::gsea::{gsea, GSEAConfig};
+    let rank_list = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(20).collect();
+    let config = GSEAConfig {
+        permutations: 50,
+        seed: Some(7),
+        ..Default::default()
+    };
+    let first = gsea(rank_list.clone(), gmt.clone(), config.clone(), None);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    let second = pool.install(|| gsea(rank_list, gmt, config, None));
+    for (a, b) in first.iter().zip(second.iter()) {
+        assert_eq!(a.p.to_bits(), b.p.to_bits());
+        assert_eq!(a.fdr.to_bits(), b.fdr.to_bits());
+        assert_eq!(a.nes.to_bits(), b.nes.to_bits());
+        assert_eq!(a.seed, Some(7));
+    }
+}
+
+#[test]
+fn gsea_leading_edge() {
+    use webgestalt_lib::methods::gsea::{
+        gsea, leading_edge_analysis, GSEAConfig, RunningSumStorage,
+    };
+    let rank_list = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(20).collect();
+    let config = GSEAConfig {
+        permutations: 10,
+        seed: Some(1),
+        running_sum: RunningSumStorage::Full,
+        ..Default::default()
+    };
+    let res = gsea(rank_list, gmt, config, None);
+    let scored: Vec<_> = res.into_iter().filter(|x| x.es != 0.0).collect();
+    for row in scored.iter() {
+        assert_eq!(row.leading_edge as usize, row.leading_edge_analytes.len());
+        assert!(row.tags > 0.0 && row.tags <= 1.0);
+        assert!(row.list > 0.0 && row.list <= 1.0);
+        assert_eq!(row.running_sum[row.rank_at_max].to_bits(), row.es.to_bits());
+    }
+    let analysis = leading_edge_analysis(&scored);
+    assert_eq!(analysis.membership.len(), analysis.analytes.len());
+    for (i, row) in scored.iter().enumerate() {
+        assert_eq!(analysis.overlap[i][i], 1.0);
+        let members = analysis.membership.iter().filter(|x| x[i]).count();
+        assert_eq!(members, row.leading_edge_analytes.len());
+    }
+}
+
+#[test]
+fn gsea_phenotype_permutation() {
+    use webgestalt_lib::methods::gsea::phenotype::{
+        gsea_phenotype, rank_metric, PhenotypeGSEAConfig, RankMetric,
+    };
+    use webgestalt_lib::methods::gsea::GSEAConfig;
+    use webgestalt_lib::readers::utils::Phenotype;
+    let matrix = webgestalt_lib::readers::read_gct_file("data/test.gct".to_string()).unwrap();
+    let phenotype = webgestalt_lib::readers::read_cls_file("data/test.cls".to_string()).unwrap();
+    assert_eq!(matrix.analytes.len(), 400);
+    assert_eq!(matrix.samples.len(), 12);
+    match &phenotype {
+        Phenotype::Categorical { classes, labels } => {
+            assert_eq!(classes, &vec!["Tumor".to_string(), "Normal".to_string()]);
+            assert_eq!(labels[0], 0);
+            assert_eq!(labels[11], 1);
+        }
+        Phenotype::Continuous { .. } => panic!("Expected a categorical phenotype"),
+    }
+    let ranks = rank_metric(&matrix, &phenotype, RankMetric::Difference);
+    assert_eq!(ranks.len(), 400);
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(6).collect();
+    let config = PhenotypeGSEAConfig {
+        gsea: GSEAConfig {
+            permutations: 100,
+            seed: Some(3),
+            ..Default::default()
+        },
+        metric: RankMetric::SignalToNoise,
+    };
+    let res = gsea_phenotype(&matrix, &phenotype, gmt.clone(), config.clone());
+    // the first set is higher and the second set is lower in the first class
+    assert!(res[0].nes > 0.0 && res[0].p < 0.05);
+    assert!(res[1].nes < 0.0 && res[1].p < 0.05);
+    assert_eq!(res[0].seed, Some(3));
+    let again = gsea_phenotype(&matrix, &phenotype, gmt, config);
+    for (a, b) in res.iter().zip(again.iter()) {
+        assert_eq!(a.p.to_bits(), b.p.to_bits());
+        assert_eq!(a.fdr.to_bits(), b.fdr.to_bits());
+    }
+}
+
+#[test]
+fn gsea_multilevel_p() {
+    use webgestalt_lib::methods::gsea::multilevel::{gsea_multilevel, MultilevelGSEAConfig};
+    use webgestalt_lib::methods::gsea::{gsea, GSEAConfig};
+    let rank_list = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(20).collect();
+    let gsea_config = GSEAConfig {
+        permutations: 200,
+        seed: Some(5),
+        ..Default::default()
+    };
+    let simple = gsea(rank_list.clone(), gmt.clone(), gsea_config.clone(), None);
+    let res = gsea_multilevel(
+        rank_list,
+        gmt,
+        MultilevelGSEAConfig {
+            gsea: gsea_config,
+            ..Default::default()
+        },
+    );
+    for (row, simple_row) in res.iter().zip(simple.iter()) {
+        assert_eq!(row.result.nes.to_bits(), simple_row.nes.to_bits());
+        assert_eq!(row.result.fdr.to_bits(), simple_row.fdr.to_bits());
+        if row.levels == 0 {
+            assert_eq!(row.result.p.to_bits(), simple_row.p.to_bits());
+        }
+    }
+    // hsa00190 is stronger than all 200 permutations, so the simple p-value is 0
+    let strong = res
+        .iter()
+        .find(|x| x.result.set == "hsa00190")
+        .expect("hsa00190 should be in the results");
+    assert!(strong.levels > 0);
+    assert!(strong.result.p > 0.0 && strong.result.p < 1e-8);
+    assert!(strong.log2err.unwrap() > 0.0);
+}
+
+#[test]
+fn gsea_multilevel_sets_with_same_id() {
+    use webgestalt_lib::methods::gsea::multilevel::{gsea_multilevel, MultilevelGSEAConfig};
+    use webgestalt_lib::methods::gsea::GSEAConfig;
+    use webgestalt_lib::readers::utils::Item;
+    let rank_list = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(20).collect();
+    let same_id: Vec<_> = gmt
+        .iter()
+        .map(|x| Item {
+            id: "same".to_owned(),
+            ..x.clone()
+        })
+        .collect();
+    let config = MultilevelGSEAConfig {
+        gsea: GSEAConfig {
+            permutations: 200,
+            seed: Some(5),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let expected = gsea_multilevel(rank_list.clone(), gmt, config.clone());
+    let res = gsea_multilevel(rank_list, same_id, config);
+    // the sets with the same ID only differ in the multilevel estimate, which hsa00190 needs
+    assert!(expected.iter().any(|x| x.levels > 0));
+    assert_eq!(res.len(), expected.len());
+    for (row, expected_row) in res.iter().zip(expected.iter()) {
+        assert_eq!(row.levels, expected_row.levels);
+        assert_eq!(row.result.p.to_bits(), expected_row.result.p.to_bits());
+    }
+}
+
+#[test]
+fn gsea_sampled_null() {
+    use webgestalt_lib::methods::gsea::{gsea, make_seeded_permutations, GSEAConfig};
+    let rank_list = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(20).collect();
+    let config = GSEAConfig {
+        permutations: 500,
+        seed: Some(11),
+        ..Default::default()
+    };
+    let permutations = make_seeded_permutations(500, rank_list.len(), 11);
+    let provided = gsea(
+        rank_list.clone(),
+        gmt.clone(),
+        config.clone(),
+        Some(permutations),
+    );
+    let sampled = gsea(rank_list, gmt, config, None);
+    // both nulls are draws from the same distribution, so the p-values agree up to Monte Carlo
+    // error
+    for (a, b) in provided.iter().zip(sampled.iter()) {
+        assert_eq!(a.es.to_bits(), b.es.to_bits());
+        assert!((a.p - b.p).abs() < 0.1, "{}: {} vs {}", a.set, a.p, b.p);
+    }
+}
+
+#[test]
+fn gsea_running_sum_storage() {
+    use webgestalt_lib::methods::gsea::{
+        enrichment_plot_data, gsea, GSEAConfig, RunningSumStorage,
+    };
+    let rank_list = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(5).collect();
+    let run = |running_sum: RunningSumStorage| {
+        let config = GSEAConfig {
+            permutations: 10,
+            seed: Some(2),
+            running_sum,
+            ..Default::default()
+        };
+        gsea(rank_list.clone(), gmt.clone(), config, None)
+    };
+    let none = run(RunningSumStorage::None);
+    let downsampled = run(RunningSumStorage::Downsampled(100));
+    let full = run(RunningSumStorage::Full);
+    let sets: Vec<String> = gmt.iter().map(|x| x.id.clone()).collect();
+    let plot = enrichment_plot_data(rank_list.clone(), &gmt, &sets, &GSEAConfig::default());
+    assert_eq!(plot.ranks.len(), rank_list.len());
+    for i in 0..gmt.len() {
+        if full[i].es == 0.0 {
+            continue;
+        }
+        assert!(none[i].running_sum.is_empty());
+        assert_eq!(full[i].running_sum.len(), rank_list.len());
+        let kept = &downsampled[i];
+        assert!(kept.running_sum.len() <= 100);
+        assert_eq!(kept.running_sum.len(), kept.running_sum_positions.len());
+        for (value, position) in kept.running_sum.iter().zip(&kept.running_sum_positions) {
+            assert_eq!(value.to_bits(), full[i].running_sum[*position].to_bits());
+        }
+        // the extrema are kept
+        assert!(kept.running_sum_positions.contains(&full[i].rank_at_max));
+        assert_eq!(plot.sets[i].set, full[i].set);
+        assert_eq!(plot.sets[i].running_sum, full[i].running_sum);
+        assert_eq!(
+            plot.sets[i].hit_positions.len(),
+            plot.sets[i]
+                .hit_positions
+                .iter()
+                .filter(|x| gmt[i].parts.contains(&plot.analytes[**x]))
+                .count()
+        );
+    }
+}
+
+#[test]
+fn gsea_prepare_rank_list() {
+    use webgestalt_lib::methods::gsea::prepare::{
+        prepare_rank_list, DuplicatePolicy, NonFinitePolicy, RankListConfig, TiePolicy,
+    };
+    use webgestalt_lib::methods::gsea::RankListItem;
+    let list: Vec<RankListItem> = [
+        ("d", 1.0),
+        ("a", -3.0),
+        ("c", 1.0),
+        ("a", 2.0),
+        ("x", f64::NAN),
+        ("b", 1.0),
+        ("y", f64::INFINITY),
+    ]
+    .iter()
+    .map(|(analyte, rank)| RankListItem {
+        analyte: analyte.to_string(),
+        rank: *rank,
+    })
+    .collect();
+    let names =
+        |list: &[RankListItem]| -> Vec<String> { list.iter().map(|x| x.analyte.clone()).collect() };
+    let (prepared, report) = prepare_rank_list(list.clone(), &RankListConfig::default()).unwrap();
+    assert_eq!(names(&prepared), vec!["b", "c", "d", "a"]);
+    assert_eq!(prepared[3].rank, -3.0);
+    assert_eq!(report.input_size, 7);
+    assert_eq!(report.output_size, 4);
+    assert_eq!(report.dropped, vec!["x", "y"]);
+    assert_eq!(report.duplicates.len(), 1);
+    assert_eq!(report.duplicates[0].entries, 2);
+    assert_eq!(report.tie_groups, 1);
+    assert_eq!(report.tied_analytes, 3);
+    let mean = RankListConfig {
+        duplicates: DuplicatePolicy::Mean,
+        ..Default::default()
+    };
+    let (prepared, _) = prepare_rank_list(list.clone(), &mean).unwrap();
+    assert_eq!(prepared[3].rank, -0.5);
+    let first = RankListConfig {
+        duplicates: DuplicatePolicy::First,
+        ..Default::default()
+    };
+    let (prepared, _) = prepare_rank_list(list.clone(), &first).unwrap();
+    assert_eq!(prepared[3].rank, -3.0);
+    let random = RankListConfig {
+        ties: TiePolicy::Random { seed: 3 },
+        ..Default::default()
+    };
+    let (a, _) = prepare_rank_list(list.clone(), &random).unwrap();
+    let mut reversed = list.clone();
+    reversed.reverse();
+    let (b, _) = prepare_rank_list(reversed, &random).unwrap();
+    // the random order of ties only depends on the seed
+    assert_eq!(names(&a), names(&b));
+    let strict = RankListConfig {
+        non_finite: NonFinitePolicy::Error,
+        ..Default::default()
+    };
+    assert!(prepare_rank_list(list.clone(), &strict).is_err());
+    let strict = RankListConfig {
+        duplicates: DuplicatePolicy::Error,
+        ..Default::default()
+    };
+    let error = prepare_rank_list(list, &strict).unwrap_err();
+    assert!(error.to_string().contains("duplicate analyte: a"));
+}
+
+#[test]
+fn gsea_excluded_sets() {
+    use webgestalt_lib::methods::gsea::multilevel::{
+        gsea_multilevel_with_excluded, MultilevelGSEAConfig,
+    };
+    use webgestalt_lib::methods::gsea::{gsea_with_excluded, GSEAConfig, GSEAExclusionReason};
+    let rank_list = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(40).collect();
+    let config = GSEAConfig {
+        min_overlap: 20,
+        max_overlap: 60,
+        permutations: 50,
+        seed: Some(5),
+        ..Default::default()
+    };
+    let (res, excluded) = gsea_with_excluded(rank_list.clone(), gmt.clone(), config.clone(), None);
+    assert_eq!(res.len() + excluded.len(), gmt.len());
+    assert!(!res.is_empty());
+    assert!(excluded
+        .iter()
+        .any(|x| x.reason == GSEAExclusionReason::OverlapTooSmall));
+    assert!(excluded
+        .iter()
+        .any(|x| x.reason == GSEAExclusionReason::OverlapTooLarge));
+    for set in &excluded {
+        match set.reason {
+            GSEAExclusionReason::OverlapTooSmall => assert!(set.overlap < 20),
+            GSEAExclusionReason::OverlapTooLarge => assert!(set.overlap > 60),
+            GSEAExclusionReason::SetLargerThanList => {}
+        }
+        assert!(res.iter().all(|x| x.set != set.set));
+    }
+    assert!(res.iter().all(|x| x.es != 0.0));
+    let (multilevel, multilevel_excluded) = gsea_multilevel_with_excluded(
+        rank_list,
+        gmt,
+        MultilevelGSEAConfig {
+            gsea: config,
+            min_exceedances: 0,
+            ..Default::default()
+        },
+    );
+    assert_eq!(multilevel.len(), res.len());
+    assert_eq!(
+        multilevel_excluded
+            .iter()
+            .map(|x| (x.set.clone(), x.overlap, x.reason))
+            .collect::<Vec<_>>(),
+        excluded
+            .iter()
+            .map(|x| (x.set.clone(), x.overlap, x.reason))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn gsea_fdr_matches_scan() {
+    use rand::{Rng, SeedableRng};
+    use webgestalt_lib::methods::gsea::nes_fdr;
+    // FDR by scanning the whole distributions for every score
+    let scan = |observed: &[f64], null: &[f64]| -> Vec<f64> {
+        observed
+            .iter()
+            .map(|nes| {
+                let top: Vec<&f64> = if *nes > 0.0 {
+                    null.iter().filter(|x| **x >= 0.0).collect()
+                } else {
+                    null.iter().filter(|x| **x < 0.0).collect()
+                };
+                let bottom: Vec<&f64> = if *nes >= 0.0 {
+                    observed.iter().filter(|x| **x >= 0.0).collect()
+                } else {
+                    observed.iter().filter(|x| **x < 0.0).collect()
+                };
+                let len = |x: &Vec<&f64>| {
+                    if x.is_empty() {
+                        0.000001
+                    } else {
+                        x.len() as f64
+                    }
+                };
+                let count =
+                    |x: &Vec<&f64>| x.iter().filter(|y| y.abs() >= nes.abs()).count() as f64;
+                let fdr = (count(&top) * len(&bottom)) / (count(&bottom) * len(&top));
+                if fdr.is_nan() {
+                    0.0
+                } else {
+                    fdr.min(1.0)
+                }
+            })
+            .collect()
+    };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(8);
+    for _ in 0..20 {
+        // coarse values give many ties, including zeros of both signs
+        let mut value = |scale: f64| (rng.gen_range(-scale..scale) * 4.0_f64).round() / 4.0;
+        let mut observed: Vec<f64> = (0..200).map(|_| value(3.0)).collect();
+        let mut null: Vec<f64> = (0..5000).map(|_| value(2.5)).collect();
+        observed.extend([0.0, -0.0, f64::NAN]);
+        null.extend([0.0, -0.0, f64::NAN]);
+        let sorted = nes_fdr(&observed, &null);
+        let scanned = scan(&observed, &null);
+        assert_eq!(
+            sorted.iter().map(|x| x.to_bits()).collect::<Vec<u64>>(),
+            scanned.iter().map(|x| x.to_bits()).collect::<Vec<u64>>()
+        );
+    }
+    // one-sided nulls leave the other side empty
+    let observed = [1.5, -0.5, 0.0];
+    let null = [0.5, 1.0, 2.0, 2.0];
+    assert_eq!(nes_fdr(&observed, &null), scan(&observed, &null));
+}
+
+#[test]
+fn gsea_non_numeric_ranks() {
+    use webgestalt_lib::methods::gsea::multilevel::{gsea_multilevel, MultilevelGSEAConfig};
+    use webgestalt_lib::methods::gsea::prepare::{
+        prepare_rank_list, NonFinitePolicy, RankListConfig,
+    };
+    use webgestalt_lib::methods::gsea::{enrichment_plot_data, gsea, GSEAConfig};
+    use webgestalt_lib::methods::mhg::{get_mhg, MHGConfig};
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("gsea_non_numeric_{}.rnk", std::process::id()));
+    std::fs::write(&path, "a\t1.5\nb\tNA\nc\t-2\n").unwrap();
+    let list =
+        webgestalt_lib::readers::read_rank_file(path.to_string_lossy().into_owned()).unwrap();
+    std::fs::write(&path, "a\t1.5\nb\n").unwrap();
+    let missing = webgestalt_lib::readers::read_rank_file(path.to_string_lossy().into_owned());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(list.len(), 3);
+    assert!(list[1].rank.is_nan());
+    assert_eq!(missing.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    let (prepared, report) = prepare_rank_list(list.clone(), &RankListConfig::default()).unwrap();
+    assert_eq!(prepared.len(), 2);
+    assert_eq!(report.dropped, vec!["b"]);
+    let strict = RankListConfig {
+        non_finite: NonFinitePolicy::Error,
+        ..Default::default()
+    };
+    assert!(prepare_rank_list(list, &strict).is_err());
+    // analytes with NaN ranks are left out, so the results match the list without them
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(5).collect();
+    let clean = webgestalt_lib::readers::read_rank_file("data/test.rnk".to_string()).unwrap();
+    let index = clean
+        .iter()
+        .position(|x| gmt[0].parts.contains(&x.analyte))
+        .unwrap();
+    let mut dirty = clean.clone();
+    dirty[index].rank = f64::NAN;
+    dirty.push(webgestalt_lib::methods::gsea::RankListItem {
+        analyte: "infinite".to_owned(),
+        rank: f64::INFINITY,
+    });
+    let mut clean = clean;
+    clean.remove(index);
+    let config = GSEAConfig {
+        permutations: 10,
+        seed: Some(1),
+        ..Default::default()
+    };
+    let expected = gsea(clean.clone(), gmt.clone(), config.clone(), None);
+    let res = gsea(dirty.clone(), gmt.clone(), config.clone(), None);
+    assert_eq!(res.len(), expected.len());
+    for (a, b) in res.iter().zip(expected.iter()) {
+        assert_eq!(a.set, b.set);
+        assert_ne!(a.es, 0.0);
+        assert_eq!(
+            (a.es, a.nes, a.p, a.fdr, a.leading_edge),
+            (b.es, b.nes, b.p, b.fdr, b.leading_edge)
+        );
+    }
+    let multilevel_config = MultilevelGSEAConfig {
+        gsea: config.clone(),
+        ..Default::default()
+    };
+    let expected = gsea_multilevel(clean.clone(), gmt.clone(), multilevel_config.clone());
+    let res = gsea_multilevel(dirty.clone(), gmt.clone(), multilevel_config);
+    assert_eq!(res.len(), expected.len());
+    for (a, b) in res.iter().zip(expected.iter()) {
+        assert_eq!(
+            (a.result.es, a.result.nes, a.result.p),
+            (b.result.es, b.result.nes, b.result.p)
+        );
+    }
+    let sets = [gmt[0].id.clone()];
+    let expected = enrichment_plot_data(clean.clone(), &gmt, &sets, &config);
+    let plot = enrichment_plot_data(dirty.clone(), &gmt, &sets, &config);
+    assert_eq!(plot.analytes, expected.analytes);
+    assert_eq!(plot.sets[0].es, expected.sets[0].es);
+    assert_eq!(plot.sets[0].hit_positions, expected.sets[0].hit_positions);
+    let mhg_config = MHGConfig {
+        min_set_size: 1,
+        ..Default::default()
+    };
+    let expected = get_mhg(clean, gmt.clone(), mhg_config.clone());
+    let res = get_mhg(dirty, gmt, mhg_config);
+    assert_eq!(res.len(), expected.len());
+    for (a, b) in res.iter().zip(expected.iter()) {
+        assert_eq!((a.mhg, a.p, a.cutoff), (b.mhg, b.p, b.cutoff));
+    }
+}
@@ -0,0 +1,129 @@
+use webgestalt_lib::methods::gsva::{
+    single_sample_scores, GSVAKernel, SingleSampleConfig, SingleSampleMethod,
+};
+
+#[test]
+fn gsva_scores() {
+    let matrix = webgestalt_lib::readers::read_gct_file("data/test.gct".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(6).collect();
+    let res = single_sample_scores(
+        &matrix,
+        gmt,
+        SingleSampleConfig {
+            method: SingleSampleMethod::GSVA {
+                kernel: GSVAKernel::Gaussian,
+                tau: 1.0,
+                max_diff: true,
+            },
+            ..Default::default()
+        },
+    );
+    assert_eq!(res.samples.len(), 12);
+    assert_eq!(res.scores.len(), res.sets.len());
+    assert!(res.scores.iter().all(|x| x.len() == 12));
+    assert!(res.scores.iter().flatten().all(|x| x.abs() <= 1.0));
+    // the first set is higher and the second set is lower in the first six (tumor) samples
+    let difference =
+        |row: &[f64]| row[..6].iter().sum::<f64>() / 6.0 - row[6..].iter().sum::<f64>() / 6.0;
+    assert_eq!(res.sets[0], "hsa00010");
+    assert!(difference(&res.scores[0]) > 0.3);
+    assert!(difference(&res.scores[1]) < -0.3);
+}
+
+#[test]
+fn ssgsea_scores() {
+    let matrix = webgestalt_lib::readers::read_gct_file("data/test.gct".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(6).collect();
+    let alpha = 0.25;
+    let res = single_sample_scores(
+        &matrix,
+        gmt.clone(),
+        SingleSampleConfig {
+            method: SingleSampleMethod::SsGSEA {
+                alpha,
+                normalize: false,
+            },
+            ..Default::default()
+        },
+    );
+    // walk the whole list of the first sample for the first set
+    let n = matrix.analytes.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| {
+        matrix.values[*b][0]
+            .partial_cmp(&matrix.values[*a][0])
+            .unwrap()
+    });
+    let hits: Vec<bool> = order
+        .iter()
+        .map(|i| gmt[0].parts.contains(&matrix.analytes[*i]))
+        .collect();
+    let weights: Vec<f64> = (0..n).map(|i| ((n - i) as f64).powf(alpha)).collect();
+    let total: f64 = (0..n).filter(|i| hits[*i]).map(|i| weights[i]).sum();
+    let misses = hits.iter().filter(|x| !**x).count() as f64;
+    let (mut inside, mut outside, mut expected) = (0.0, 0.0, 0.0);
+    for i in 0..n {
+        if hits[i] {
+            inside += weights[i] / total;
+        } else {
+            outside += 1.0 / misses;
+        }
+        expected += inside - outside;
+    }
+    assert!((res.scores[0][0] - expected).abs() < 1e-6 * expected.abs());
+    let normalized = single_sample_scores(
+        &matrix,
+        gmt,
+        SingleSampleConfig {
+            method: SingleSampleMethod::SsGSEA {
+                alpha,
+                normalize: true,
+            },
+            ..Default::default()
+        },
+    );
+    let range = |scores: &[Vec<f64>]| {
+        let all: Vec<f64> = scores.iter().flatten().copied().collect();
+        all.iter().cloned().fold(f64::MIN, f64::max) - all.iter().cloned().fold(f64::MAX, f64::min)
+    };
+    assert!((range(&normalized.scores) - 1.0).abs() < 1e-9);
+    assert!(range(&res.scores) > 1.0);
+}
+
+#[test]
+fn single_sample_non_finite_values() {
+    let matrix = webgestalt_lib::readers::read_gct_file("data/test.gct".to_string()).unwrap();
+    let gmt = webgestalt_lib::readers::read_gmt_file("data/ktest.gmt".to_string()).unwrap();
+    let gmt: Vec<_> = gmt.into_iter().take(6).collect();
+    let row = matrix
+        .analytes
+        .iter()
+        .position(|x| gmt[0].parts.contains(x))
+        .unwrap();
+    let mut dirty = matrix.clone();
+    dirty.values[row][3] = f64::NAN;
+    let mut clean = matrix;
+    clean.analytes.remove(row);
+    clean.descriptions.remove(row);
+    clean.values.remove(row);
+    // analytes with a NaN value are left out, so the scores match the matrix without them
+    for method in [
+        SingleSampleConfig::default().method,
+        SingleSampleMethod::SsGSEA {
+            alpha: 0.25,
+            normalize: false,
+        },
+    ] {
+        let config = SingleSampleConfig {
+            method,
+            ..Default::default()
+        };
+        let expected = single_sample_scores(&clean, gmt.clone(), config.clone());
+        let res = single_sample_scores(&dirty, gmt.clone(), config);
+        assert_eq!(res.sets, expected.sets);
+        assert_eq!(res.scores, expected.scores);
+        assert!(res.scores.iter().flatten().all(|x| x.is_finite()));
+    }
+}
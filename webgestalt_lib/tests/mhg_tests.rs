@@ -0,0 +1,58 @@
+use statrs::assert_almost_eq;
+use webgestalt_lib::methods::gsea::RankListItem;
+use webgestalt_lib::methods::mhg::{get_mhg, MHGConfig};
+use webgestalt_lib::methods::ora::ora_p;
+use webgestalt_lib::readers::utils::Item;
+const THRESHOLD: f64 = 1e-9;
+
+fn brute_force_statistic(positions: &[usize], big_n: usize) -> f64 {
+    let k = positions.len();
+    positions
+        .iter()
+        .enumerate()
+        .map(|(b, n)| ora_p(big_n as i64, k as i64, *n as i64 + 1, b as i64 + 1))
+        .fold(1.0, f64::min)
+}
+
+#[test]
+fn mhg_matches_enumeration() {
+    let big_n = 12;
+    let positions = vec![0, 2, 3, 9];
+    let analyte_list: Vec<RankListItem> = (0..big_n)
+        .map(|i| RankListItem {
+            analyte: format!("g{}", i),
+            rank: (big_n - i) as f64,
+        })
+        .collect();
+    let gmt = vec![Item {
+        id: "set".to_owned(),
+        url: String::new(),
+        parts: positions.iter().map(|i| format!("g{}", i)).collect(),
+    }];
+    let res = get_mhg(
+        analyte_list,
+        gmt,
+        MHGConfig {
+            min_set_size: 1,
+            ..Default::default()
+        },
+    );
+    let observed = brute_force_statistic(&positions, big_n);
+    // enumerate all placements of 4 set analytes in 12 positions
+    let mut total = 0;
+    let mut extreme = 0;
+    for mask in 0_u32..(1 << big_n) {
+        if mask.count_ones() != 4 {
+            continue;
+        }
+        let placed: Vec<usize> = (0..big_n).filter(|i| mask & (1 << i) != 0).collect();
+        total += 1;
+        if brute_force_statistic(&placed, big_n) <= observed * (1.0 + 1e-10) {
+            extreme += 1;
+        }
+    }
+    assert_almost_eq!(res[0].mhg, observed, THRESHOLD);
+    assert_almost_eq!(res[0].p, extreme as f64 / total as f64, THRESHOLD);
+    assert_eq!(res[0].cutoff, 4);
+    assert_eq!(res[0].overlap, 3);
+}
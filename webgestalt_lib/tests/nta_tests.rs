@@ -0,0 +1,213 @@
+use ndarray::{Array1, Array2, Axis};
+use rand::{Rng, SeedableRng};
+use webgestalt_lib::methods::nta::{
+    get_nta, get_nta_enrichment, process_nta, NTAConfig, NTAMethod,
+};
+use webgestalt_lib::methods::ora::ORAConfig;
+use webgestalt_lib::readers::utils::Item;
+
+/// Random walk with restart on the dense adjacency matrix, as NTA originally computed it
+fn dense_walk(
+    edges: &[Vec<String>],
+    seeds: &[String],
+    r: f64,
+    tolerance: f64,
+) -> Vec<(String, f64)> {
+    let mut nodes: Vec<String> = edges.iter().flatten().cloned().collect();
+    nodes.sort();
+    nodes.dedup();
+    let index = |x: &String| nodes.binary_search(x).unwrap();
+    let mut graph = Array2::<f64>::zeros((nodes.len(), nodes.len()));
+    for edge in edges {
+        graph[[index(&edge[0]), index(&edge[1])]] = 1.0;
+        graph[[index(&edge[1]), index(&edge[0])]] = 1.0;
+    }
+    let de = graph.sum_axis(Axis(0)).insert_axis(Axis(1));
+    let w = (&graph.t() / &de).t().to_owned();
+    let mut p0 = Array1::<f64>::zeros(nodes.len());
+    for seed in seeds {
+        p0[index(seed)] = 1.0 / seeds.len() as f64;
+    }
+    let mut pt = p0.clone();
+    let mut pt1 = w.dot(&pt) * (1.0 - r) + (r * &p0);
+    while (&pt1 - &pt).mapv(f64::abs).sum() > tolerance {
+        pt = pt1;
+        pt1 = w.dot(&pt) * (1.0 - r) + (r * &p0);
+    }
+    nodes.into_iter().zip(pt1).collect()
+}
+
+fn random_edges(nodes: usize, edges: usize, seed: u64) -> Vec<Vec<String>> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut edge_list: Vec<Vec<String>> = (0..edges)
+        .map(|_| {
+            vec![
+                format!("n{}", rng.gen_range(0..nodes)),
+                format!("n{}", rng.gen_range(0..nodes)),
+            ]
+        })
+        .collect();
+    // repeated edges and self loops
+    edge_list.push(edge_list[0].clone());
+    edge_list.push(vec!["n1".to_string(), "n1".to_string()]);
+    edge_list
+}
+
+#[test]
+fn nta_sparse_matches_dense() {
+    let edge_list = random_edges(60, 200, 4);
+    let seeds: Vec<String> = ["n1", "n2", "n3", "n5", "n8"]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+    let config = NTAConfig {
+        edge_list: edge_list.clone(),
+        seeds: seeds.clone(),
+        ..Default::default()
+    };
+    let sparse = process_nta(config.clone());
+    let mut dense = dense_walk(
+        &edge_list,
+        &seeds,
+        config.reset_probability,
+        config.tolerance,
+    );
+    assert_eq!(sparse.len(), dense.len());
+    // sparse results are sorted by probability
+    assert!(sparse.windows(2).all(|x| x[0].1 >= x[1].1));
+    let mut sparse = sparse;
+    sparse.sort_by(|a, b| a.0.cmp(&b.0));
+    dense.sort_by(|a, b| a.0.cmp(&b.0));
+    for (a, b) in sparse.iter().zip(dense.iter()) {
+        assert_eq!(a.0, b.0);
+        assert!((a.1 - b.1).abs() < 1e-12, "{}: {} != {}", a.0, a.1, b.1);
+    }
+    let total: f64 = sparse.iter().map(|x| x.1).sum();
+    assert!((total - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn nta_expand_and_prioritize() {
+    let edge_list = random_edges(60, 200, 9);
+    let seeds: Vec<String> = ["n1", "n2", "n3"].iter().map(|x| x.to_string()).collect();
+    let expand = get_nta(NTAConfig {
+        edge_list: edge_list.clone(),
+        seeds: seeds.clone(),
+        method: Some(NTAMethod::Expand(5)),
+        ..Default::default()
+    });
+    assert_eq!(expand.neighborhood.len(), 5);
+    assert!(expand.neighborhood.iter().all(|x| !seeds.contains(x)));
+    let prioritize = get_nta(NTAConfig {
+        edge_list,
+        seeds: seeds.clone(),
+        method: Some(NTAMethod::Prioritize(2)),
+        ..Default::default()
+    });
+    assert_eq!(prioritize.neighborhood.len(), 2);
+    assert!(prioritize.neighborhood.iter().all(|x| seeds.contains(x)));
+}
+
+fn edges(list: &[&str]) -> Vec<Vec<String>> {
+    list.iter()
+        .map(|x| x.split_whitespace().map(|y| y.to_string()).collect())
+        .collect()
+}
+
+fn walk(edge_list: Vec<Vec<String>>, directed: bool) -> ahash::AHashMap<String, f64> {
+    process_nta(NTAConfig {
+        edge_list,
+        seeds: vec!["a".to_string()],
+        directed,
+        ..Default::default()
+    })
+    .into_iter()
+    .collect()
+}
+
+#[test]
+fn nta_weighted_directed() {
+    let unweighted = walk(edges(&["a b", "a c", "b c", "c d"]), false);
+    let ones = walk(edges(&["a b 1", "a c 1", "b c 1.0", "c d 1"]), false);
+    for (node, p) in unweighted.iter() {
+        assert!((p - ones[node]).abs() < 1e-12);
+    }
+    // the heavier edge carries more of the walk
+    let weighted = walk(edges(&["a b 9", "a c 1", "b c 1", "c d 1"]), false);
+    assert!(weighted["b"] > unweighted["b"]);
+    assert!(weighted["b"] > weighted["c"]);
+    // nothing reaches the source of an edge into the seed
+    let directed = walk(edges(&["a b", "b c", "d a"]), true);
+    assert_eq!(directed["d"], 0.0);
+    assert!(directed["c"] > 0.0);
+    let undirected = walk(edges(&["a b", "b c", "d a"]), false);
+    assert!(undirected["d"] > 0.0);
+    // out-degree normalization splits the step of a over its two targets by weight
+    let split = walk(edges(&["a b 3", "a c 1"]), true);
+    assert!((split["a"] - 0.5).abs() < 1e-12);
+    assert!((split["b"] - 0.1875).abs() < 1e-12);
+    assert!((split["c"] - 0.0625).abs() < 1e-12);
+}
+
+#[test]
+fn nta_neighborhood_enrichment() {
+    // two cliques of eight nodes joined by one edge
+    let mut edge_list = Vec::new();
+    for prefix in ["m", "x"] {
+        for i in 0..8 {
+            for j in (i + 1)..8 {
+                edge_list.push(vec![format!("{}{}", prefix, i), format!("{}{}", prefix, j)]);
+            }
+        }
+    }
+    edge_list.push(vec!["m7".to_string(), "x0".to_string()]);
+    let set = |id: &str, prefix: &str| Item {
+        id: id.to_string(),
+        url: String::new(),
+        parts: (0..8)
+            .map(|i| format!("{}{}", prefix, i))
+            .chain(["outside".to_string()])
+            .collect(),
+    };
+    let gmt = vec![set("module", "m"), set("other", "x")];
+    let res = get_nta_enrichment(
+        NTAConfig {
+            edge_list,
+            seeds: vec!["m0".to_string(), "m1".to_string(), "m2".to_string()],
+            method: Some(NTAMethod::Expand(5)),
+            ..Default::default()
+        },
+        gmt,
+        ORAConfig::default(),
+    );
+    assert_eq!(res.network.neighborhood.len(), 5);
+    assert!(res.network.neighborhood.iter().all(|x| x.starts_with('m')));
+    let module = res.enrichment.iter().find(|x| x.set == "module").unwrap();
+    // the reference is the 16 network nodes, so the analyte outside the network is not counted
+    assert_eq!(module.reference_set_size, 8);
+    assert_eq!(module.overlap, 5);
+    assert!((module.p - 56.0 / 4368.0).abs() < 1e-9);
+    assert!(module.fdr < 0.05);
+}
+
+#[test]
+fn nta_weight_column_is_not_a_node() {
+    let edge_list = edges(&["a b 0.9", "a c 0.4", "b c 0.7", "c d 0.15"]);
+    let walk = process_nta(NTAConfig {
+        edge_list: edge_list.clone(),
+        seeds: vec!["a".to_string()],
+        ..Default::default()
+    });
+    let mut nodes: Vec<&str> = walk.iter().map(|x| x.0.as_str()).collect();
+    nodes.sort_unstable();
+    assert_eq!(nodes, vec!["a", "b", "c", "d"]);
+    let expand = get_nta(NTAConfig {
+        edge_list,
+        seeds: vec!["a".to_string()],
+        method: Some(NTAMethod::Expand(6)),
+        ..Default::default()
+    });
+    let mut neighborhood = expand.neighborhood.clone();
+    neighborhood.sort();
+    assert_eq!(neighborhood, vec!["b", "c", "d"]);
+}
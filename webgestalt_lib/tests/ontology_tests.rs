@@ -0,0 +1,90 @@
+use ahash::AHashSet;
+use webgestalt_lib::methods::ontology::{
+    get_ontology_ora, DecorrelationMethod, OntologyORAConfig, OntologyORAResult,
+};
+use webgestalt_lib::methods::ora::ORAConfig;
+use webgestalt_lib::readers::utils::Item;
+
+fn genes(range: std::ops::Range<usize>) -> Vec<String> {
+    range.map(|i| format!("g{}", i)).collect()
+}
+
+fn run(method: DecorrelationMethod) -> Vec<OntologyORAResult> {
+    let reference: AHashSet<String> = genes(0..100).into_iter().collect();
+    let interest: AHashSet<String> = genes(0..10).into_iter().collect();
+    let mut parent = genes(0..8);
+    parent.extend(genes(20..40));
+    let gmt = vec![
+        Item {
+            id: "child".to_owned(),
+            url: String::new(),
+            parts: genes(0..8),
+        },
+        Item {
+            id: "parent".to_owned(),
+            url: String::new(),
+            parts: parent,
+        },
+        Item {
+            id: "root".to_owned(),
+            url: String::new(),
+            parts: genes(0..60),
+        },
+    ];
+    let hierarchy = vec![
+        ("child".to_owned(), "parent".to_owned()),
+        ("parent".to_owned(), "root".to_owned()),
+    ];
+    let config = OntologyORAConfig {
+        ora: ORAConfig {
+            min_overlap: 0,
+            min_set_size: 1,
+            ..Default::default()
+        },
+        method,
+        ..Default::default()
+    };
+    get_ontology_ora(&interest, &reference, gmt, &hierarchy, config)
+}
+
+#[test]
+fn elim_removes_child_analytes() {
+    let res = run(DecorrelationMethod::Elim);
+    let child = res.iter().find(|x| x.classic.set == "child").unwrap();
+    let parent = res.iter().find(|x| x.classic.set == "parent").unwrap();
+    assert_eq!(child.classic.p, child.decorrelated_p);
+    assert!(parent.classic.p < 0.01);
+    assert_eq!(parent.decorrelated_p, 1.0);
+}
+
+#[test]
+fn weight_downweights_parent() {
+    let res = run(DecorrelationMethod::Weight);
+    let child = res.iter().find(|x| x.classic.set == "child").unwrap();
+    let parent = res.iter().find(|x| x.classic.set == "parent").unwrap();
+    assert!(child.decorrelated_p < 0.01);
+    assert!(parent.decorrelated_p > parent.classic.p);
+}
+
+#[test]
+fn read_hierarchy() {
+    use webgestalt_lib::readers::read_hierarchy_file;
+    let path = std::env::temp_dir().join(format!("hierarchy_{}.txt", std::process::id()));
+    let path_string = path.to_string_lossy().into_owned();
+    std::fs::write(&path, "child\tparent\nparent\troot\n").unwrap();
+    let pairs = read_hierarchy_file(path_string.clone()).unwrap();
+    std::fs::write(&path, "child\tparent\nparent\n").unwrap();
+    let malformed = read_hierarchy_file(path_string);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        pairs,
+        vec![
+            ("child".to_owned(), "parent".to_owned()),
+            ("parent".to_owned(), "root".to_owned())
+        ]
+    );
+    assert_eq!(
+        malformed.unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
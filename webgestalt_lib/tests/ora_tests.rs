@@ -16,3 +16,254 @@ fn ora() {
     assert_eq!(gmtcount, 850);
     assert_almost_eq!(res.p, 0.004516370110462129, THRESHOLD);
 }
+
+#[test]
+fn ora_filters_sets() {
+    use webgestalt_lib::methods::ora::ORAExclusionReason;
+    let (gmt, gene_list, reference) = webgestalt_lib::readers::read_ora_files(
+        "data/test.gmt".to_owned(),
+        "data/genelist.txt".to_owned(),
+        "data/reference.txt".to_owned(),
+    );
+    let gmtcount: usize = gmt.len();
+    let config = ORAConfig {
+        min_set_size: 30,
+        max_set_size: 100,
+        ..Default::default()
+    };
+    let (res, excluded) =
+        webgestalt_lib::methods::ora::get_ora_with_excluded(&gene_list, &reference, gmt, config);
+    assert_eq!(res.len() + excluded.len(), gmtcount);
+    let find = |set: &str| excluded.iter().find(|x| x.set == set).unwrap();
+    // 29 analytes in the reference
+    let small = find("GO:0000002");
+    assert_eq!(small.reason, ORAExclusionReason::SetTooSmall);
+    assert_eq!((small.set_size, small.overlap), (29, 2));
+    // 109 analytes in the reference
+    let large = find("GO:0000041");
+    assert_eq!(large.reason, ORAExclusionReason::SetTooLarge);
+    assert_eq!((large.set_size, large.overlap), (109, 1));
+    // 41 analytes in the reference, none in the interest list
+    let no_overlap = find("GO:0000959");
+    assert_eq!(no_overlap.reason, ORAExclusionReason::OverlapTooSmall);
+    assert_eq!((no_overlap.set_size, no_overlap.overlap), (41, 0));
+    assert!(res
+        .iter()
+        .all(|x| (30..=100).contains(&x.reference_set_size) && x.overlap >= 5));
+    assert!(res.iter().all(|x| excluded.iter().all(|y| y.set != x.set)));
+}
+
+#[test]
+fn ora_overlap_ids() {
+    let (gmt, gene_list, reference) = webgestalt_lib::readers::read_ora_files(
+        "data/test.gmt".to_owned(),
+        "data/genelist.txt".to_owned(),
+        "data/reference.txt".to_owned(),
+    );
+    let res = webgestalt_lib::methods::ora::get_ora(
+        &gene_list,
+        &reference,
+        gmt.clone(),
+        ORAConfig::default(),
+    );
+    let row = res.iter().find(|x| x.set == "GO:2000147").unwrap();
+    assert_eq!(row.overlap_ids.len() as i64, row.overlap);
+    assert!(row.overlap_ids.iter().all(|x| gene_list.contains(x)));
+    assert!(row.set_size >= row.reference_set_size);
+    let compact = webgestalt_lib::methods::ora::get_ora(
+        &gene_list,
+        &reference,
+        gmt,
+        ORAConfig {
+            report_overlap_ids: false,
+            ..Default::default()
+        },
+    );
+    assert!(compact.iter().all(|x| x.overlap_ids.is_empty()));
+}
+
+#[test]
+fn ora_directions() {
+    use webgestalt_lib::methods::ora::{ora_p_directed, ORATestDirection};
+    // Exact values for a reference of 50, a set of 5 and an interest list of 10
+    assert_almost_eq!(
+        ora_p_directed(50, 5, 10, 5, ORATestDirection::Over),
+        0.0001189,
+        THRESHOLD
+    );
+    assert_almost_eq!(
+        ora_p_directed(50, 5, 10, 4, ORATestDirection::TwoSided),
+        0.0040835,
+        THRESHOLD
+    );
+    let under = ora_p_directed(50, 5, 10, 0, ORATestDirection::Under);
+    let over = ora_p_directed(50, 5, 10, 1, ORATestDirection::Over);
+    assert_almost_eq!(under + over, 1.0, THRESHOLD);
+}
+
+#[test]
+fn ora_depletion_config() {
+    use ahash::AHashSet;
+    use webgestalt_lib::methods::ora::{get_ora, ORATestDirection};
+    use webgestalt_lib::readers::utils::Item;
+    let reference: AHashSet<String> = (0..100).map(|i| format!("g{}", i)).collect();
+    let interest: AHashSet<String> = (0..50).map(|i| format!("g{}", i)).collect();
+    // the set has no analytes in the interest list, but half of the reference is interesting
+    let gmt = vec![Item {
+        id: "depleted".to_owned(),
+        url: String::new(),
+        parts: (50..70).map(|i| format!("g{}", i)).collect(),
+    }];
+    let over = ORAConfig::for_direction(ORATestDirection::Over);
+    assert_eq!(over.min_overlap, ORAConfig::default().min_overlap);
+    assert!(get_ora(&interest, &reference, gmt.clone(), over).is_empty());
+    for direction in [ORATestDirection::Under, ORATestDirection::TwoSided] {
+        let config = ORAConfig::for_direction(direction);
+        assert_eq!(config.min_overlap, 0);
+        assert_eq!(config.direction, direction);
+        let res = get_ora(&interest, &reference, gmt.clone(), config);
+        assert_eq!(res.len(), 1);
+        assert!(res[0].p < 1e-6);
+    }
+}
+
+#[test]
+fn ora_prepared_library() {
+    use webgestalt_lib::methods::ora::{get_ora_prepared, library::ORALibrary};
+    let (gmt, gene_list, reference) = webgestalt_lib::readers::read_ora_files(
+        "data/test.gmt".to_owned(),
+        "data/genelist.txt".to_owned(),
+        "data/reference.txt".to_owned(),
+    );
+    let library = ORALibrary::new(&gmt);
+    let (res, _) = get_ora_prepared(
+        &library,
+        &library.bitset(&gene_list),
+        &library.bitset(&reference),
+        ORAConfig::default(),
+    );
+    let expected =
+        webgestalt_lib::methods::ora::get_ora(&gene_list, &reference, gmt, ORAConfig::default());
+    assert_eq!(res.len(), expected.len());
+    for (a, b) in res.iter().zip(expected.iter()) {
+        assert_eq!(a.set, b.set);
+        assert_eq!(a.p, b.p);
+        assert_eq!(a.overlap_ids, b.overlap_ids);
+    }
+}
+
+#[test]
+fn ora_batch() {
+    use ahash::{AHashMap, AHashSet};
+    use webgestalt_lib::methods::ora::{get_ora_batch, library::ORALibrary};
+    let (gmt, gene_list, reference) = webgestalt_lib::readers::read_ora_files(
+        "data/test.gmt".to_owned(),
+        "data/genelist.txt".to_owned(),
+        "data/reference.txt".to_owned(),
+    );
+    let half: AHashSet<String> = gene_list
+        .iter()
+        .take(gene_list.len() / 2)
+        .cloned()
+        .collect();
+    let mut lists: AHashMap<String, AHashSet<String>> = AHashMap::default();
+    lists.insert("full".to_owned(), gene_list.clone());
+    lists.insert("half".to_owned(), half.clone());
+    let library = ORALibrary::new(&gmt);
+    let batch = get_ora_batch(
+        &library,
+        &lists,
+        &library.bitset(&reference),
+        ORAConfig::default(),
+    );
+    for (name, list) in [("full", gene_list), ("half", half)] {
+        let single = webgestalt_lib::methods::ora::get_ora(
+            &list,
+            &reference,
+            gmt.clone(),
+            ORAConfig::default(),
+        );
+        let (res, _) = &batch[name];
+        assert_eq!(res.len(), single.len());
+        for (a, b) in res.iter().zip(single.iter()) {
+            assert_eq!(a.set, b.set);
+            assert_eq!(a.fdr, b.fdr);
+        }
+    }
+}
+
+#[test]
+fn ora_empirical() {
+    use ahash::AHashMap;
+    use webgestalt_lib::methods::ora::empirical::{get_ora_empirical, EmpiricalORAConfig};
+    let (gmt, gene_list, reference) = webgestalt_lib::readers::read_ora_files(
+        "data/test.gmt".to_owned(),
+        "data/genelist.txt".to_owned(),
+        "data/reference.txt".to_owned(),
+    );
+    let config = EmpiricalORAConfig {
+        max_resamples: 2000,
+        seed: 42,
+        ..Default::default()
+    };
+    let (res, _) = get_ora_empirical(&gene_list, &reference, gmt.clone(), None, config.clone());
+    let (again, _) = get_ora_empirical(&gene_list, &reference, gmt.clone(), None, config.clone());
+    let row = res.iter().find(|x| x.result.set == "GO:2000147").unwrap();
+    assert_almost_eq!(row.result.p, 0.004516370110462129, 0.005);
+    assert!(row.resamples <= 2000);
+    for (a, b) in res.iter().zip(again.iter()) {
+        assert_eq!(a.result.p, b.result.p);
+        assert_eq!(a.resamples, b.resamples);
+    }
+    let covariate: AHashMap<String, f64> = reference
+        .iter()
+        .map(|x| (x.clone(), x.len() as f64))
+        .collect();
+    let (matched, _) = get_ora_empirical(&gene_list, &reference, gmt, Some(&covariate), config);
+    assert_eq!(matched.len(), res.len());
+}
+
+#[test]
+fn ora_empirical_sets_with_same_id() {
+    use ahash::AHashSet;
+    use webgestalt_lib::methods::ora::empirical::{get_ora_empirical, EmpiricalORAConfig};
+    use webgestalt_lib::readers::utils::Item;
+    let reference: AHashSet<String> = (0..200).map(|i| format!("g{}", i)).collect();
+    let interest: AHashSet<String> = (0..10).map(|i| format!("g{}", i)).collect();
+    // the first set contains the whole interest list, the second none of it
+    let gmt = vec![
+        Item {
+            id: "same".to_owned(),
+            url: String::new(),
+            parts: (0..20).map(|i| format!("g{}", i)).collect(),
+        },
+        Item {
+            id: "same".to_owned(),
+            url: String::new(),
+            parts: (100..120).map(|i| format!("g{}", i)).collect(),
+        },
+    ];
+    let max_resamples = 500;
+    let (res, _) = get_ora_empirical(
+        &interest,
+        &reference,
+        gmt,
+        None,
+        EmpiricalORAConfig {
+            ora: ORAConfig {
+                min_overlap: 0,
+                ..Default::default()
+            },
+            max_resamples,
+            seed: 1,
+            ..Default::default()
+        },
+    );
+    assert_eq!(res.len(), 2);
+    assert_eq!(res[0].result.overlap, 10);
+    assert_eq!(res[0].resamples, max_resamples);
+    assert_almost_eq!(res[0].result.p, 1.0 / (max_resamples + 1) as f64, 1e-12);
+    assert_eq!(res[1].result.overlap, 0);
+    assert!(res[1].resamples < max_resamples);
+    assert_almost_eq!(res[1].result.p, 1.0, 1e-12);
+}
@@ -0,0 +1,83 @@
+use pretty_assertions::assert_eq;
+use webgestalt_lib::methods::ora::ORAResult;
+use webgestalt_lib::methods::redundancy::{reduce_redundancy, RedundancyMethod};
+use webgestalt_lib::readers::utils::Item;
+
+fn make_set(id: &str, parts: &[&str]) -> Item {
+    Item {
+        id: id.to_owned(),
+        url: String::new(),
+        parts: parts.iter().map(|x| x.to_string()).collect(),
+    }
+}
+
+fn make_result(set: &str, p: f64) -> ORAResult {
+    ORAResult {
+        set: set.to_owned(),
+        p,
+        fdr: p,
+        overlap: 0,
+        expected: 0.0,
+        enrichment_ratio: 0.0,
+        set_size: 0,
+        reference_set_size: 0,
+        overlap_ids: Vec::new(),
+        odds_ratio: 0.0,
+        odds_ratio_ci_low: 0.0,
+        odds_ratio_ci_high: 0.0,
+    }
+}
+
+fn redundant_sets() -> (Vec<Item>, Vec<ORAResult>) {
+    let gmt = vec![
+        make_set("A1", &["a", "b", "c", "d", "e"]),
+        make_set("A2", &["a", "b", "c", "d", "f"]),
+        make_set("A3", &["a", "b", "c", "e", "f"]),
+        make_set("B1", &["v", "w", "x", "y", "z"]),
+        make_set("B2", &["v", "w", "x", "y"]),
+    ];
+    let results = vec![
+        make_result("A1", 0.001),
+        make_result("A2", 0.01),
+        make_result("A3", 0.02),
+        make_result("B1", 0.03),
+        make_result("B2", 0.0001),
+    ];
+    (gmt, results)
+}
+
+#[test]
+fn affinity_propagation() {
+    let (gmt, results) = redundant_sets();
+    let clusters = reduce_redundancy(&results, &gmt, RedundancyMethod::AffinityPropagation);
+    assert_eq!(clusters.len(), 2);
+    let mut sizes: Vec<usize> = clusters.iter().map(|x| x.members.len()).collect();
+    sizes.sort();
+    assert_eq!(sizes, vec![2, 3]);
+}
+
+#[test]
+fn weighted_set_cover() {
+    let (gmt, results) = redundant_sets();
+    let clusters = reduce_redundancy(&results, &gmt, RedundancyMethod::WeightedSetCover(10));
+    assert_eq!(clusters[0].representative, "B2");
+    assert!(clusters
+        .iter()
+        .all(|x| x.members.contains(&x.representative)));
+    let total: usize = clusters.iter().map(|x| x.members.len()).sum();
+    assert_eq!(total, 5);
+}
+
+#[test]
+fn weighted_set_cover_disjoint_set() {
+    let (mut gmt, mut results) = redundant_sets();
+    gmt.push(make_set("C1", &["p", "q"]));
+    results.push(make_result("C1", 0.5));
+    // only two sets are selected, so C1 shares no analytes with any representative
+    let clusters = reduce_redundancy(&results, &gmt, RedundancyMethod::WeightedSetCover(2));
+    let summary: Vec<(&str, usize)> = clusters
+        .iter()
+        .map(|x| (x.representative.as_str(), x.members.len()))
+        .collect();
+    assert_eq!(summary, vec![("B2", 2), ("A1", 3), ("C1", 1)]);
+}